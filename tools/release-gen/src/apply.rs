@@ -0,0 +1,201 @@
+use {
+    crate::{archive::ReleaseArchive, release_manifest::Action, tree::TreeSnapshot},
+    anyhow::Context,
+    sha2::{Digest, Sha256},
+    std::{
+        fs::{File, Permissions},
+        io,
+        os::unix::fs::{FileExt, PermissionsExt},
+        path::Path,
+    },
+};
+
+/// Length of the header `updiff` puts in front of the bsdiff patch.
+pub const UPDIFF_HEADER_LEN: usize = 216;
+
+/// Applies the release to the tree in `dir`, modifying it in place.
+///
+/// This is what the device does when installing a release, minus the device
+/// state actions (`update-bt`, `set` and `open-app`), which are skipped.
+/// Releases touching paths outside of `dir` are rejected before any action is
+/// applied.
+pub fn apply_release(release: &ReleaseArchive, dir: &Path) -> anyhow::Result<()> {
+    check_paths(&release.manifest.actions)?;
+    apply_actions(release, &release.manifest.actions, dir)
+}
+
+fn check_paths(actions: &[Action]) -> anyhow::Result<()> {
+    for action in actions {
+        match action {
+            Action::Transaction { actions } => check_paths(actions)?,
+            _ => {
+                if let Some(path) = action.unsafe_path() {
+                    anyhow::bail!("{} leaves the tree: {path}", action.name());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn apply_actions(release: &ReleaseArchive, actions: &[Action], dir: &Path) -> anyhow::Result<()> {
+    for action in actions {
+        apply_action(release, action, dir).with_context(|| format!("Applying {action:?}"))?;
+    }
+    Ok(())
+}
+
+fn apply_action(release: &ReleaseArchive, action: &Action, dir: &Path) -> anyhow::Result<()> {
+    match action {
+        Action::Transaction { actions } => apply_actions(release, actions, dir)?,
+        Action::Patch {
+            patch_file,
+            patch_source,
+            ..
+        } => {
+            let patched = patch(&release.payload(patch_file)?, &read(dir, patch_source)?)?;
+            write(dir, patch_source, &patched)?;
+        }
+        Action::PatchAdd {
+            patch_file,
+            patch_source,
+            dest,
+            ..
+        } => {
+            let patched = patch(&release.payload(patch_file)?, &read(dir, patch_source)?)?;
+            write_new(dir, dest, &patched)?;
+        }
+        Action::PatchChunk {
+            patch_file,
+            patch_source,
+            offset,
+            base_length,
+            base_sha256,
+            new_length,
+            new_sha256,
+            file_size,
+            ..
+        } => {
+            let file = File::options()
+                .read(true)
+                .write(true)
+                .open(dir.join(patch_source))
+                .with_context(|| format!("Opening {patch_source}"))?;
+            let window_hash = |length| -> anyhow::Result<String> {
+                let mut window = vec![0; usize::try_from(length)?];
+                file.read_exact_at(&mut window, *offset)
+                    .map(|()| hex::encode(Sha256::digest(&window)))
+                    .or_else(|err| match err.kind() {
+                        io::ErrorKind::UnexpectedEof => Ok(String::new()),
+                        _ => Err(err),
+                    })
+                    .with_context(|| format!("Reading {patch_source} at {offset}"))
+            };
+
+            // A chunk already applied by an interrupted update is skipped.
+            if window_hash(*new_length)? != *new_sha256 {
+                anyhow::ensure!(
+                    window_hash(*base_length)? == *base_sha256,
+                    "{patch_source} at {offset} is neither the base nor the new window"
+                );
+                let mut base = vec![0; usize::try_from(*base_length)?];
+                file.read_exact_at(&mut base, *offset)
+                    .with_context(|| format!("Reading {patch_source} at {offset}"))?;
+                let patched = patch(&release.payload(patch_file)?, &base)?;
+                anyhow::ensure!(
+                    hex::encode(Sha256::digest(&patched)) == *new_sha256,
+                    "Patched window of {patch_source} at {offset} does not match its hash"
+                );
+                file.write_all_at(&patched, *offset)
+                    .with_context(|| format!("Writing {patch_source} at {offset}"))?;
+            }
+            if offset + new_length == *file_size {
+                file.set_len(*file_size)
+                    .with_context(|| format!("Truncating {patch_source}"))?;
+            }
+        }
+        Action::Add { source, dest } => write_new(dir, dest, &release.payload(source)?)?,
+        Action::Replace { source, dest, .. } => write(dir, dest, &release.payload(source)?)?,
+        Action::Delete { path } => {
+            std::fs::remove_file(dir.join(path)).with_context(|| format!("Deleting {path}"))?
+        }
+        Action::Rename { source, dest } | Action::Move { source, dest } => {
+            anyhow::ensure!(!dir.join(dest).exists(), "{dest} already exists");
+            create_parent(dir, dest)?;
+            std::fs::rename(dir.join(source), dir.join(dest))
+                .with_context(|| format!("Renaming {source} to {dest}"))?;
+        }
+        Action::Copy { source, dest } => write_new(dir, dest, &read(dir, source)?)?,
+        Action::Symlink { path, target } => {
+            anyhow::ensure!(
+                dir.join(path).symlink_metadata().is_err(),
+                "{path} already exists"
+            );
+            create_parent(dir, path)?;
+            std::os::unix::fs::symlink(target, dir.join(path))
+                .with_context(|| format!("Linking {path} to {target}"))?;
+        }
+        Action::SetMode { path, mode } => {
+            let mode = u32::from_str_radix(mode, 8)
+                .with_context(|| format!("Invalid mode for {path}: {mode}"))?;
+            std::fs::set_permissions(dir.join(path), Permissions::from_mode(mode))
+                .with_context(|| format!("Setting the mode of {path}"))?;
+        }
+        Action::UpdateBt | Action::Set { .. } | Action::OpenApp { .. } => {}
+    }
+    Ok(())
+}
+
+/// Applies an `updiff` patch to `base`.
+pub fn patch(patch: &[u8], base: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut bsdiff_patch = patch
+        .get(UPDIFF_HEADER_LEN..)
+        .context("Patch is shorter than the updiff header")?;
+    let mut patched = Vec::with_capacity(base.len());
+    bsdiff::patch(base, &mut bsdiff_patch, &mut patched).context("Applying bsdiff patch")?;
+    Ok(patched)
+}
+
+/// Copies all the regular files, with their modes, and the links of the
+/// `snapshot` into `dest`.
+pub fn copy_tree(snapshot: &TreeSnapshot, dest: &Path) -> anyhow::Result<()> {
+    for file in &snapshot.files {
+        let dest_file = dest.join(&file.path);
+        let parent = dest_file.parent().expect("File should have a parent");
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating dir: {}", parent.display()))?;
+        std::fs::copy(snapshot.full_path(&file.path), &dest_file)
+            .with_context(|| format!("Copying file to: {}", dest_file.display()))?;
+        std::fs::set_permissions(&dest_file, Permissions::from_mode(file.mode))
+            .with_context(|| format!("Setting the mode of: {}", dest_file.display()))?;
+    }
+    for link in &snapshot.links {
+        let dest_link = dest.join(&link.path);
+        let parent = dest_link.parent().expect("Link should have a parent");
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating dir: {}", parent.display()))?;
+        std::os::unix::fs::symlink(&link.target, &dest_link)
+            .with_context(|| format!("Creating link: {}", dest_link.display()))?;
+    }
+    Ok(())
+}
+
+fn read(dir: &Path, path: &str) -> anyhow::Result<Vec<u8>> {
+    std::fs::read(dir.join(path)).with_context(|| format!("Reading {path}"))
+}
+
+fn write(dir: &Path, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+    create_parent(dir, path)?;
+    std::fs::write(dir.join(path), contents).with_context(|| format!("Writing {path}"))
+}
+
+fn write_new(dir: &Path, path: &str, contents: &[u8]) -> anyhow::Result<()> {
+    anyhow::ensure!(!dir.join(path).exists(), "{path} already exists");
+    write(dir, path, contents)
+}
+
+fn create_parent(dir: &Path, path: &str) -> anyhow::Result<()> {
+    let file = dir.join(path);
+    let parent = file.parent().expect("File should have a parent");
+    std::fs::create_dir_all(parent).with_context(|| format!("Creating dir: {}", parent.display()))
+}
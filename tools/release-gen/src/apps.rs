@@ -0,0 +1,47 @@
+use {crate::tree::TreeSnapshot, anyhow::Context, serde::Deserialize, std::collections::BTreeSet};
+
+/// The part of an app's `manifest.json` release-gen cares about.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AppManifest {
+    app_id: String,
+}
+
+/// Returns the `appId` of every app in the tree, read from the
+/// `apps/*/manifest.json` files.
+pub fn app_ids(tree: &TreeSnapshot) -> anyhow::Result<BTreeSet<String>> {
+    let mut ids = BTreeSet::new();
+    for file in &tree.files {
+        let mut components = file.path.components();
+        let is_app_manifest = components.next().is_some_and(|c| c.as_os_str() == "apps")
+            && components.next().is_some()
+            && components
+                .next()
+                .is_some_and(|c| c.as_os_str() == "manifest.json")
+            && components.next().is_none();
+        if !is_app_manifest {
+            continue;
+        }
+
+        let path = tree.full_path(&file.path);
+        let manifest: AppManifest = serde_json::from_slice(
+            &std::fs::read(&path)
+                .with_context(|| format!("Reading app manifest: {}", path.display()))?,
+        )
+        .with_context(|| format!("Parsing app manifest: {}", path.display()))?;
+        ids.insert(manifest.app_id.to_lowercase());
+    }
+    Ok(ids)
+}
+
+/// Checks that the app `app_id` is in the tree.
+pub fn ensure_app_exists(tree: &TreeSnapshot, app_id: &str) -> anyhow::Result<()> {
+    let ids = app_ids(tree)?;
+    anyhow::ensure!(
+        ids.contains(&app_id.to_lowercase()),
+        "No app with appId {app_id} in {}. Apps found: {}",
+        tree.root.display(),
+        ids.into_iter().collect::<Vec<_>>().join(", ")
+    );
+    Ok(())
+}
@@ -0,0 +1,91 @@
+use {
+    crate::release_manifest::ReleaseManifest,
+    anyhow::Context,
+    std::{
+        borrow::Cow,
+        collections::BTreeMap,
+        fs::File,
+        io::Read,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Name of the directory inside of the release tar holding the patches and
+/// the added files.
+pub const PATCH_DIR: &str = "patch";
+/// Name of the manifest file inside of the release tar.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// A release tar read into memory.
+#[derive(Debug)]
+pub struct ReleaseArchive {
+    pub manifest: ReleaseManifest,
+    /// Contents of the files under `patch/`, keyed by their path relative to
+    /// `patch/`. These are the names used by the `patch-file` and `source`
+    /// fields of the actions.
+    pub payloads: BTreeMap<String, Vec<u8>>,
+}
+
+impl ReleaseArchive {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file =
+            File::open(path).with_context(|| format!("Opening release: {}", path.display()))?;
+        Self::read(file).with_context(|| format!("Reading release: {}", path.display()))
+    }
+
+    pub fn read(reader: impl Read) -> anyhow::Result<Self> {
+        let mut tar = tar::Archive::new(reader);
+        let mut manifest = None;
+        let mut payloads = BTreeMap::new();
+
+        for entry in tar.entries().context("Reading tar entries")? {
+            let mut entry = entry.context("Reading tar entry")?;
+            if entry.header().entry_type().is_dir() {
+                continue;
+            }
+            let path = entry.path().context("Reading tar entry path")?.into_owned();
+            let mut contents = vec![];
+            entry
+                .read_to_end(&mut contents)
+                .with_context(|| format!("Reading tar entry: {}", path.display()))?;
+
+            if path == Path::new(MANIFEST_FILE) {
+                manifest = Some(ReleaseManifest::from_slice(&contents)?);
+            } else if let Ok(payload) = path.strip_prefix(PATCH_DIR) {
+                payloads.insert(payload_name(payload)?, contents);
+            } else {
+                anyhow::bail!("Unexpected entry in the release tar: {}", path.display());
+            }
+        }
+
+        Ok(Self {
+            manifest: manifest.context("Release tar has no manifest.json")?,
+            payloads,
+        })
+    }
+
+    /// Contents of the payload `name`, decompressed if the manifest says it
+    /// is compressed.
+    pub fn payload(&self, name: &str) -> anyhow::Result<Cow<'_, [u8]>> {
+        let payload = self
+            .payloads
+            .get(name)
+            .with_context(|| format!("Release tar has no {PATCH_DIR}/{name}"))?;
+        match self.manifest.compression.get(name) {
+            Some(compression) => compression
+                .decompress(payload)
+                .map(Cow::Owned)
+                .with_context(|| format!("Reading {PATCH_DIR}/{name}")),
+            None => Ok(Cow::Borrowed(payload)),
+        }
+    }
+}
+
+fn payload_name(path: &Path) -> anyhow::Result<String> {
+    path.to_str().map(str::to_string).with_context(|| {
+        format!(
+            "Non UTF-8 path in the release tar: {}",
+            PathBuf::from(path).display()
+        )
+    })
+}
@@ -0,0 +1,294 @@
+use {
+    crate::{
+        archive::ReleaseArchive,
+        constraints::Violation,
+        release_manifest::{Action, Compression},
+    },
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        fmt,
+    },
+};
+
+/// A problem found in a release tar.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Issue {
+    /// An action refers to a file that is not under `patch/`.
+    MissingPayload { action: &'static str, name: String },
+    /// A file under `patch/` is not referenced by any action.
+    UnreferencedPayload { name: String },
+    /// Two actions write the same destination.
+    ConflictingWrites { path: String },
+    /// An action uses a path that an earlier action deleted.
+    UseAfterDelete { action: &'static str, path: String },
+    /// A transaction without any actions.
+    EmptyTransaction,
+    /// An action uses an absolute path or one with a `..` component, or
+    /// creates a symlink pointing above the root of the tree.
+    UnsafePath { action: &'static str, path: String },
+    /// A `set` action changes a setting the release config doesn't list.
+    UnknownSetting { setting: String },
+    /// Two `set` actions set the same setting to different values.
+    ConflictingSettings { setting: String },
+    /// A rollback that doesn't name the version it can be installed on.
+    UnconstrainedRollback,
+    /// The chunks of a file are out of order or disagree on its size.
+    InconsistentChunks { path: String },
+    /// A compressed payload can't be decompressed with its codec.
+    UndecodablePayload {
+        name: String,
+        compression: Compression,
+    },
+    /// The device does not meet a release constraint.
+    NotApplicable(Violation),
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Issue::MissingPayload { action, name } => {
+                write!(
+                    f,
+                    "`{action}` refers to patch/{name}, which is not in the release"
+                )
+            }
+            Issue::UnreferencedPayload { name } => {
+                write!(f, "patch/{name} is not referenced by any action")
+            }
+            Issue::ConflictingWrites { path } => {
+                write!(f, "{path} is written by more than one action")
+            }
+            Issue::UseAfterDelete { action, path } => {
+                write!(f, "`{action}` uses {path} after it was deleted")
+            }
+            Issue::EmptyTransaction => write!(f, "transaction has no actions"),
+            Issue::UnsafePath { action, path } => {
+                write!(f, "`{action}` uses {path}, which is outside of the tree")
+            }
+            Issue::UnknownSetting { setting } => write!(f, "{setting} is not a known setting"),
+            Issue::ConflictingSettings { setting } => {
+                write!(f, "{setting} is set to more than one value")
+            }
+            Issue::UnconstrainedRollback => {
+                write!(f, "rollback can be installed on any version")
+            }
+            Issue::InconsistentChunks { path } => {
+                write!(
+                    f,
+                    "chunks of {path} are out of order or disagree on its size"
+                )
+            }
+            Issue::UndecodablePayload { name, compression } => {
+                write!(
+                    f,
+                    "patch/{name} can't be decompressed with {}",
+                    compression.name()
+                )
+            }
+            Issue::NotApplicable(violation) => write!(f, "{violation}"),
+        }
+    }
+}
+
+/// Checks the consistency of the manifest and the payloads of the release.
+pub fn check_release(release: &ReleaseArchive) -> Vec<Issue> {
+    run_checks(release, None)
+}
+
+/// Like [`check_release`], and also checks that `set` actions only change the
+/// `known_settings`.
+pub fn check_release_with_settings(
+    release: &ReleaseArchive,
+    known_settings: &BTreeSet<String>,
+) -> Vec<Issue> {
+    run_checks(release, Some(known_settings))
+}
+
+fn run_checks<'a>(
+    release: &'a ReleaseArchive,
+    known_settings: Option<&'a BTreeSet<String>>,
+) -> Vec<Issue> {
+    let mut checker = Checker {
+        known_settings,
+        ..Default::default()
+    };
+    checker.check_actions(release, &release.manifest.actions);
+
+    for name in release.payloads.keys() {
+        if !checker.referenced.contains(name.as_str()) {
+            checker
+                .issues
+                .push(Issue::UnreferencedPayload { name: name.clone() });
+        }
+    }
+    // A rollback installed on any other version than the one it rolls back
+    // from would leave the device with a broken tree.
+    if release.manifest.rollback && release.manifest.constraints.from_versions.is_empty() {
+        checker.issues.push(Issue::UnconstrainedRollback);
+    }
+    for (name, compression) in &release.manifest.compression {
+        if !release.payloads.contains_key(name) {
+            checker.issues.push(Issue::MissingPayload {
+                action: "compression",
+                name: name.clone(),
+            });
+        } else if release.payload(name).is_err() {
+            checker.issues.push(Issue::UndecodablePayload {
+                name: name.clone(),
+                compression: *compression,
+            });
+        }
+    }
+    for (path, writes) in &checker.writes {
+        if *writes > 1 {
+            checker
+                .issues
+                .push(Issue::ConflictingWrites { path: path.clone() });
+        }
+    }
+
+    checker.issues
+}
+
+#[derive(Default)]
+struct Checker<'a> {
+    /// Settings `set` actions may change, if checked.
+    known_settings: Option<&'a BTreeSet<String>>,
+    issues: Vec<Issue>,
+    referenced: BTreeSet<&'a str>,
+    deleted: BTreeSet<&'a str>,
+    writes: BTreeMap<String, usize>,
+    settings: BTreeMap<&'a str, &'a str>,
+    /// Offset of the last chunk of every chunked file, and its size.
+    chunks: BTreeMap<&'a str, (u64, u64)>,
+}
+
+impl<'a> Checker<'a> {
+    fn check_actions(&mut self, release: &ReleaseArchive, actions: &'a [Action]) {
+        for action in actions {
+            self.check_action(release, action);
+        }
+    }
+
+    fn check_action(&mut self, release: &ReleaseArchive, action: &'a Action) {
+        if let Some(path) = action.unsafe_path() {
+            self.issues.push(Issue::UnsafePath {
+                action: action.name(),
+                path: path.to_string(),
+            });
+        }
+        match action {
+            // Nested transactions are checked like top-level ones.
+            Action::Transaction { actions } => {
+                if actions.is_empty() {
+                    self.issues.push(Issue::EmptyTransaction);
+                }
+                self.check_actions(release, actions);
+            }
+            Action::Patch {
+                patch_file,
+                patch_source,
+                ..
+            } => {
+                self.payload(release, action, patch_file);
+                self.read(action, patch_source);
+                self.write(patch_source);
+            }
+            Action::PatchAdd {
+                patch_file,
+                patch_source,
+                dest,
+                ..
+            } => {
+                self.payload(release, action, patch_file);
+                self.read(action, patch_source);
+                self.write(dest);
+            }
+            Action::PatchChunk {
+                patch_file,
+                patch_source,
+                offset,
+                file_size,
+                ..
+            } => {
+                self.payload(release, action, patch_file);
+                self.read(action, patch_source);
+                // All chunks of a file together are a single write of it.
+                match self.chunks.insert(patch_source, (*offset, *file_size)) {
+                    None => self.write(patch_source),
+                    Some((previous, size)) if previous >= *offset || size != *file_size => {
+                        self.issues.push(Issue::InconsistentChunks {
+                            path: patch_source.clone(),
+                        });
+                    }
+                    Some(_) => {}
+                }
+            }
+            Action::Add { source, dest } => {
+                self.payload(release, action, source);
+                self.write(dest);
+            }
+            Action::Replace { source, dest, .. } => {
+                self.payload(release, action, source);
+                self.write(dest);
+            }
+            Action::Delete { path } => {
+                self.read(action, path);
+                self.deleted.insert(path);
+            }
+            Action::Rename { source, dest } | Action::Move { source, dest } => {
+                self.read(action, source);
+                self.deleted.insert(source);
+                self.write(dest);
+            }
+            Action::Copy { source, dest } => {
+                self.read(action, source);
+                self.write(dest);
+            }
+            Action::Symlink { path, .. } => self.write(path),
+            Action::SetMode { path, .. } => self.read(action, path),
+            Action::Set { setting, value } => {
+                if let Some(known) = self.known_settings
+                    && !known.contains(setting)
+                {
+                    self.issues.push(Issue::UnknownSetting {
+                        setting: setting.clone(),
+                    });
+                }
+                if let Some(previous) = self.settings.insert(setting, value)
+                    && previous != value
+                {
+                    self.issues.push(Issue::ConflictingSettings {
+                        setting: setting.clone(),
+                    });
+                }
+            }
+            Action::UpdateBt | Action::OpenApp { .. } => {}
+        }
+    }
+
+    fn payload(&mut self, release: &ReleaseArchive, action: &Action, name: &'a str) {
+        self.referenced.insert(name);
+        if !release.payloads.contains_key(name) {
+            self.issues.push(Issue::MissingPayload {
+                action: action.name(),
+                name: name.to_string(),
+            });
+        }
+    }
+
+    fn read(&mut self, action: &Action, path: &str) {
+        if self.deleted.contains(path) {
+            self.issues.push(Issue::UseAfterDelete {
+                action: action.name(),
+                path: path.to_string(),
+            });
+        }
+    }
+
+    fn write(&mut self, path: &'a str) {
+        // Writing a path that was deleted before is how a file is replaced.
+        self.deleted.remove(path);
+        *self.writes.entry(path.to_string()).or_default() += 1;
+    }
+}
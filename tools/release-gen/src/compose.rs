@@ -0,0 +1,288 @@
+use {
+    crate::{
+        MetadataPolicy,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check_updiff,
+        constraints::compare_versions,
+        generate_release,
+        release::BuildReport,
+        release_manifest::{Action, Compression, Constraints, Label, LocalizedText},
+        report::ReportFormat,
+        settings,
+        tree::TreeSnapshot,
+    },
+    anyhow::Context,
+    std::{
+        cmp::Ordering,
+        num::NonZeroU64,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Chained releases to compose into one, see [`compose`].
+#[derive(Debug, Clone)]
+pub struct ComposeRequest {
+    /// Path to the directory of the version the first release updates from.
+    /// The intermediate trees are reconstructed from it.
+    pub base: PathBuf,
+    /// Release tars to compose, in the order they would be applied.
+    pub releases: Vec<PathBuf>,
+    /// Version before the update. Defaults to the base version of the first
+    /// release.
+    pub base_version: Option<String>,
+    /// Version after the update. Defaults to the new version of the last
+    /// release.
+    pub new_version: Option<String>,
+    /// Label of the composed release. Defaults to the label of the last
+    /// release. The release notes are always taken from the last release.
+    pub label: Option<String>,
+    /// Path where the composed release tar should be created.
+    pub out: PathBuf,
+    /// Path to the `updiff` tool binary.
+    pub updiff_path: PathBuf,
+    /// Format version of the composed `manifest.json`.
+    pub format_version: u32,
+    /// Directory where patches are cached across runs.
+    pub patch_cache: Option<PathBuf>,
+    /// Compress the payloads of the composed release with this codec.
+    pub compression: Option<Compression>,
+    /// Patch files larger than this in windows of this size.
+    pub chunk_size: Option<NonZeroU64>,
+    /// Reports of the composed release written next to it.
+    pub report: Vec<ReportFormat>,
+    /// Date of the composed release, as `YYYY-MM-DD`.
+    pub date: Option<String>,
+}
+
+/// Composes chained releases into a single release going directly from the
+/// base version of the first release to the new version of the last one.
+///
+/// The releases are applied one after another to a copy of the base tree and
+/// the composed release is generated by diffing the base tree against the
+/// reconstructed final tree. This collapses redundant actions: a patch
+/// followed by a delete becomes a delete, an add followed by a patch becomes
+/// an add of the final content, an add followed by a delete disappears, and
+/// chained patches become a single patch regenerated between the base and the
+/// final file.
+///
+/// The composed release is verified by applying it to another copy of the base
+/// tree and comparing the result with the reconstructed final tree.
+pub fn compose(args: ComposeRequest) -> anyhow::Result<BuildReport> {
+    check_updiff(&args.updiff_path)?;
+
+    let releases = args
+        .releases
+        .iter()
+        .map(|path| ReleaseArchive::open(path))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let versions: Vec<_> = releases
+        .iter()
+        .map(|release| release.manifest.versions())
+        .collect();
+    for (i, pair) in versions.windows(2).enumerate() {
+        if let [Some((_, prev_new)), Some((next_base, _))] = pair {
+            anyhow::ensure!(
+                prev_new == next_base,
+                "{} updates to {prev_new}, but {} updates from {next_base}",
+                args.releases[i].display(),
+                args.releases[i + 1].display(),
+            );
+        }
+    }
+    let base_version = args
+        .base_version
+        .or_else(|| versions[0].map(|(base, _)| base.to_string()))
+        .context("Base version can't be inferred from the first release, use --base-version")?;
+    let new_version = args
+        .new_version
+        .or_else(|| versions[versions.len() - 1].map(|(_, new)| new.to_string()))
+        .context("New version can't be inferred from the last release, use --new-version")?;
+
+    let mut report = BuildReport::default();
+    let base = report
+        .timings
+        .time("snapshot", || TreeSnapshot::new(&args.base))
+        .context("Reading base dir")?;
+    let work_dir = tempfile::tempdir().context("Creating temporary dir")?;
+
+    let final_dir = work_dir.path().join("final");
+    let new = report.timings.time("reconstruct", || {
+        copy_tree(&base, &final_dir)?;
+        for (release, path) in releases.iter().zip(&args.releases) {
+            apply_release(release, &final_dir)
+                .with_context(|| format!("Reconstructing the tree after {}", path.display()))?;
+        }
+        TreeSnapshot::new(&final_dir).context("Reading reconstructed tree")
+    })?;
+
+    let last = &releases[releases.len() - 1].manifest;
+    let (label, localized_label) = match (args.label, &last.label) {
+        (Some(label), _) => (Some(label), LocalizedText::new()),
+        (None, Label::Text(label)) => (Some(label.clone()), LocalizedText::new()),
+        (None, Label::Localized(labels)) => (None, labels.clone()),
+    };
+    let mut options = ReleaseOptions {
+        label,
+        localized_label,
+        notes: last.notes.clone(),
+        mandatory: releases.iter().any(|release| release.manifest.mandatory),
+        updiff_path: args.updiff_path,
+        format_version: args.format_version,
+        constraints: compose_constraints(&releases)?,
+        // `update-bt` is carried over from the composed releases.
+        bt_firmware: vec![],
+        open_app: None,
+        open_app_route: None,
+        exclude: vec![],
+        include: vec![],
+        no_default_excludes: false,
+        metadata_policy: Some(MetadataPolicy::Actions),
+        jobs: None,
+        patch_cache: args.patch_cache,
+        compression: args.compression,
+        chunk_size: args.chunk_size,
+        report: args.report,
+        with_rollback: false,
+        is_rollback: false,
+        commits: Default::default(),
+        date: args.date,
+    };
+    let mut device_actions = compose_device_actions(&releases)?;
+    if let Some(Action::OpenApp { app_id, route }) =
+        device_actions.pop_if(|action| matches!(action, Action::OpenApp { .. }))
+    {
+        options.open_app = Some(app_id);
+        options.open_app_route = Some(route);
+    }
+    report.add(generate_release(
+        &base_version,
+        &base,
+        &new_version,
+        &new,
+        &options,
+        device_actions,
+        &args.out,
+    )?);
+
+    let verified = report.timings.time("verify", || {
+        verify(&args.out, &base, &new, &work_dir.path().join("verify"))
+    });
+    if let Err(err) = verified {
+        let err = match std::fs::remove_file(&args.out) {
+            Ok(()) => err,
+            Err(remove_err) => err.context(format!(
+                "Removing {} failed too: {remove_err}",
+                args.out.display()
+            )),
+        };
+        return Err(err.context("Verifying the composed release"));
+    }
+
+    Ok(report)
+}
+
+/// Combines the constraints of the releases so that the composed release can
+/// only be installed where the whole chain could be: on the versions the first
+/// release can be installed on, on the hardware revisions all releases support,
+/// with the newest bootloader any release needs and until the first release in
+/// the chain expires.
+fn compose_constraints(releases: &[ReleaseArchive]) -> anyhow::Result<Constraints> {
+    let mut composed = releases[0].manifest.constraints.clone();
+
+    for release in &releases[1..] {
+        let constraints = &release.manifest.constraints;
+        if composed.hardware_revisions.is_empty() {
+            composed
+                .hardware_revisions
+                .clone_from(&constraints.hardware_revisions);
+        } else if !constraints.hardware_revisions.is_empty() {
+            composed
+                .hardware_revisions
+                .retain(|revision| constraints.hardware_revisions.contains(revision));
+            anyhow::ensure!(
+                !composed.hardware_revisions.is_empty(),
+                "The releases have no hardware revision in common"
+            );
+        }
+        if let Some(min) = &constraints.min_bootloader_version
+            && composed
+                .min_bootloader_version
+                .as_ref()
+                .is_none_or(|composed| compare_versions(min, composed) == Ordering::Greater)
+        {
+            composed.min_bootloader_version = Some(min.clone());
+        }
+        if let Some(expires) = &constraints.expires
+            && composed
+                .expires
+                .as_ref()
+                .is_none_or(|composed| expires < composed)
+        {
+            composed.expires = Some(expires.clone());
+        }
+    }
+
+    Ok(composed)
+}
+
+/// Collects the actions that change the device state rather than files. A
+/// single `update-bt` is kept, `set` actions of the same setting must agree
+/// on the value and only the last `open-app` is kept, at the end.
+pub fn compose_device_actions(releases: &[ReleaseArchive]) -> anyhow::Result<Vec<Action>> {
+    let mut actions = vec![];
+    for release in releases {
+        collect_device_actions(&release.manifest.actions, &mut actions);
+    }
+
+    let mut composed: Vec<Action> = vec![];
+    for action in actions {
+        match &action {
+            Action::Set { setting, value } => {
+                settings::push_set(&mut composed, setting, value)
+                    .context("Composing the settings migrations")?;
+            }
+            Action::UpdateBt if composed.contains(&action) => {}
+            Action::OpenApp { .. } => {
+                composed.retain(|kept| !matches!(kept, Action::OpenApp { .. }));
+                composed.push(action);
+            }
+            _ => composed.push(action),
+        }
+    }
+    // The app is opened once everything else is done.
+    composed.sort_by_key(|action| matches!(action, Action::OpenApp { .. }));
+    Ok(composed)
+}
+
+fn collect_device_actions(actions: &[Action], out: &mut Vec<Action>) {
+    for action in actions {
+        match action {
+            Action::Transaction { actions } => collect_device_actions(actions, out),
+            Action::UpdateBt | Action::Set { .. } | Action::OpenApp { .. } => {
+                out.push(action.clone())
+            }
+            _ => {}
+        }
+    }
+}
+
+fn verify(
+    release: &Path,
+    base: &TreeSnapshot,
+    expected: &TreeSnapshot,
+    dir: &Path,
+) -> anyhow::Result<()> {
+    let release = ReleaseArchive::open(release)?;
+    copy_tree(base, dir)?;
+    apply_release(&release, dir)?;
+    let applied = TreeSnapshot::new(dir).context("Reading the tree with the release applied")?;
+    anyhow::ensure!(
+        applied.same_as(expected),
+        "Applying the composed release does not produce the same tree as applying the releases \
+         one by one"
+    );
+    Ok(())
+}
@@ -0,0 +1,79 @@
+use {
+    crate::release_manifest::Compression,
+    anyhow::Context,
+    std::io::{Read, Write},
+    xz2::stream::{Check, Filters, LzmaOptions, Stream},
+};
+
+/// Log2 of the largest window (zstd) or dictionary (xz) a payload may need to
+/// be decompressed, 1 MiB. This is part of the release format, see the
+/// "Compression" section of the README: the device decompresses a payload
+/// while applying its action, next to the windows of a chunked patch.
+pub const MAX_WINDOW_LOG: u32 = 20;
+/// zstd level used for payloads. The window is capped at [`MAX_WINDOW_LOG`]
+/// instead of the 8 MiB the level would use by default.
+const ZSTD_LEVEL: i32 = 19;
+/// xz preset used for payloads. The dictionary is capped at
+/// [`MAX_WINDOW_LOG`] instead of the 8 MiB the preset would use by default.
+const XZ_PRESET: u32 = 6;
+/// Memory the xz decoder may use. liblzma only limits the memory, which is
+/// the dictionary plus well under 256 KiB of decoder state, and the next
+/// dictionary size LZMA2 can encode after 1 MiB is 1.5 MiB, so this admits
+/// exactly the dictionaries up to [`MAX_WINDOW_LOG`].
+const XZ_MEMLIMIT: u64 = (1 << MAX_WINDOW_LOG) + (256 << 10);
+
+impl Compression {
+    /// Name of the codec as it appears in the manifest.
+    pub fn name(self) -> &'static str {
+        match self {
+            Compression::Zstd => "zstd",
+            Compression::Xz => "xz",
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Compression::Zstd => {
+                let mut encoder = zstd::Encoder::new(vec![], ZSTD_LEVEL)?;
+                encoder.window_log(MAX_WINDOW_LOG)?;
+                encoder.set_pledged_src_size(Some(data.len() as u64))?;
+                encoder.include_contentsize(true)?;
+                encoder.write_all(data).context("Compressing with zstd")?;
+                encoder.finish().context("Compressing with zstd")
+            }
+            Compression::Xz => {
+                let mut options = LzmaOptions::new_preset(XZ_PRESET)?;
+                options.dict_size(1 << MAX_WINDOW_LOG);
+                let stream =
+                    Stream::new_stream_encoder(Filters::new().lzma2(&options), Check::Crc64)?;
+                let mut encoder = xz2::write::XzEncoder::new_stream(vec![], stream);
+                encoder.write_all(data).context("Compressing with xz")?;
+                encoder.finish().context("Compressing with xz")
+            }
+        }
+    }
+
+    pub fn decompress(self, data: &[u8]) -> anyhow::Result<Vec<u8>> {
+        match self {
+            // Payloads needing a bigger window than the device has are
+            // rejected, so that `check` and `apply` catch them.
+            Compression::Zstd => {
+                let mut decoder = zstd::Decoder::new(data)?;
+                decoder.window_log_max(MAX_WINDOW_LOG)?;
+                let mut decoded = vec![];
+                decoder
+                    .read_to_end(&mut decoded)
+                    .context("Decompressing with zstd")?;
+                Ok(decoded)
+            }
+            Compression::Xz => {
+                let stream = Stream::new_stream_decoder(XZ_MEMLIMIT, 0)?;
+                let mut decoded = vec![];
+                xz2::read::XzDecoder::new_stream(data, stream)
+                    .read_to_end(&mut decoded)
+                    .context("Decompressing with xz")?;
+                Ok(decoded)
+            }
+        }
+    }
+}
@@ -0,0 +1,253 @@
+use {
+    crate::{
+        MetadataPolicy,
+        release_manifest::{Action, Constraints, LocalizedText, parse_date},
+        settings,
+    },
+    anyhow::Context,
+    serde::Deserialize,
+    std::{
+        collections::{BTreeMap, BTreeSet},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Name of the release config file found at the root of each version folder.
+pub const RELEASE_CONFIG_FILE: &str = "release-config.toml";
+/// Prefix of the per-language release notes files next to the release config,
+/// e.g. `release-notes.en.md`.
+pub const RELEASE_NOTES_PREFIX: &str = "release-notes.";
+
+/// Contents of `release-config.toml`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReleaseConfig {
+    pub release: ReleaseSection,
+    /// Default constraints of the release, overridden by the command line.
+    #[serde(default)]
+    pub constraints: Constraints,
+    #[serde(default)]
+    pub bluetooth: BluetoothSection,
+    #[serde(default)]
+    pub files: FilesSection,
+    /// App opened after the update.
+    pub open_app: Option<OpenAppSection>,
+    #[serde(default)]
+    pub settings: SettingsSection,
+    /// Settings changed when updating to this version.
+    #[serde(default)]
+    pub migrations: Vec<Migration>,
+    /// Release notes read from the `release-notes.<language>.md` files.
+    #[serde(skip)]
+    pub notes: ReleaseNotes,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct BluetoothSection {
+    /// Glob patterns matching the Bluetooth controller firmware in the tree.
+    #[serde(default)]
+    pub firmware: Vec<String>,
+}
+
+/// Gitignore-style patterns of the files left out of the release.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FilesSection {
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// Files put into the release even if they match an exclude pattern.
+    #[serde(default)]
+    pub include: Vec<String>,
+    pub metadata_policy: Option<MetadataPolicy>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OpenAppSection {
+    /// `appId` of the app, as in its `manifest.json`.
+    pub app_id: String,
+    pub route: String,
+}
+
+/// Settings of the version, as declared by KeyOS.
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct SettingsSection {
+    /// Names of the settings KeyOS knows about, the only ones migrations may
+    /// change.
+    #[serde(default)]
+    pub known: BTreeSet<String>,
+}
+
+/// Settings changed when updating to the version of the release config,
+/// turned into `set` actions.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Migration {
+    /// Base versions the migration applies to. Applies to every base version
+    /// if empty.
+    #[serde(default)]
+    pub from_versions: Vec<String>,
+    /// New values by setting name.
+    pub settings: BTreeMap<String, String>,
+}
+
+impl Migration {
+    fn applies_to(&self, base_version: &str) -> bool {
+        self.from_versions.is_empty()
+            || self.from_versions.iter().any(|version| {
+                version.trim_start_matches('v') == base_version.trim_start_matches('v')
+            })
+    }
+}
+
+/// Labels and release notes read from the `release-notes.<language>.md` files.
+/// The first line of each file is a `# ` heading with the label of the
+/// release, the rest of the file are the release notes.
+#[derive(Debug, Default)]
+pub struct ReleaseNotes {
+    pub labels: LocalizedText,
+    pub notes: LocalizedText,
+}
+
+impl ReleaseNotes {
+    /// Reads all the release notes files in the version folder.
+    pub fn load(version_dir: &Path) -> anyhow::Result<Self> {
+        let mut release_notes = Self::default();
+
+        for entry in std::fs::read_dir(version_dir)
+            .with_context(|| format!("Reading dir: {}", version_dir.display()))?
+        {
+            let entry = entry?;
+            let file_name = entry.file_name();
+            let Some(language) = file_name
+                .to_str()
+                .and_then(|name| name.strip_prefix(RELEASE_NOTES_PREFIX))
+                .and_then(|name| name.strip_suffix(".md"))
+            else {
+                continue;
+            };
+            anyhow::ensure!(
+                !language.is_empty()
+                    && language
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || c == '-'),
+                "Invalid language in release notes file name: {}",
+                entry.path().display()
+            );
+
+            let contents = std::fs::read_to_string(entry.path())
+                .with_context(|| format!("Reading release notes: {}", entry.path().display()))?;
+            let (heading, notes) = contents.split_once('\n').unwrap_or((&contents, ""));
+            let label = heading.strip_prefix("# ").with_context(|| {
+                format!(
+                    "Release notes should start with a `# ` heading: {}",
+                    entry.path().display()
+                )
+            })?;
+            release_notes
+                .labels
+                .insert(language.to_string(), label.trim().to_string());
+            release_notes
+                .notes
+                .insert(language.to_string(), notes.trim().to_string());
+        }
+
+        Ok(release_notes)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReleaseSection {
+    /// The version this release is normally updated from.
+    pub base_version: Option<String>,
+    /// All versions this release can be updated from. Takes precedence over
+    /// `base-version` when generating releases from several bases.
+    #[serde(default)]
+    pub base_versions: Vec<String>,
+    pub version: String,
+}
+
+impl ReleaseConfig {
+    /// Loads `release-config.toml` from the root of the version folder.
+    pub fn load(version_dir: &Path) -> anyhow::Result<Self> {
+        let path = version_dir.join(RELEASE_CONFIG_FILE);
+        let config = std::fs::read_to_string(&path)
+            .with_context(|| format!("Reading release config: {}", path.display()))?;
+        let mut config: Self = toml::from_str(&config)
+            .with_context(|| format!("Parsing release config: {}", path.display()))?;
+        // Validated like `--expires`, as the device compares the dates as
+        // strings.
+        if let Some(expires) = &mut config.constraints.expires {
+            *expires = parse_date(expires)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| {
+                    format!("Invalid `[constraints] expires` in {}", path.display())
+                })?;
+        }
+        for migration in &config.migrations {
+            for setting in migration.settings.keys() {
+                anyhow::ensure!(
+                    config.settings.known.contains(setting),
+                    "Setting {setting} in the migrations of {} is not in `[settings] known`",
+                    path.display()
+                );
+            }
+        }
+        config.notes = ReleaseNotes::load(version_dir)?;
+        Ok(config)
+    }
+
+    /// Like [`ReleaseConfig::load`], but returns `None` if the version folder
+    /// has no `release-config.toml`.
+    pub fn load_optional(version_dir: &Path) -> anyhow::Result<Option<Self>> {
+        if !version_dir.join(RELEASE_CONFIG_FILE).exists() {
+            return Ok(None);
+        }
+        Self::load(version_dir).map(Some)
+    }
+
+    /// Returns the base versions and their folders. Version folders are
+    /// expected to be siblings of the `version_dir`.
+    pub fn bases(&self, version_dir: &Path) -> anyhow::Result<Vec<(String, PathBuf)>> {
+        let versions = if self.release.base_versions.is_empty() {
+            self.release.base_version.iter().cloned().collect()
+        } else {
+            self.release.base_versions.clone()
+        };
+        anyhow::ensure!(
+            !versions.is_empty(),
+            "No base versions in {}",
+            version_dir.join(RELEASE_CONFIG_FILE).display()
+        );
+
+        let parent = version_dir
+            .parent()
+            .context("Version folder should have a parent")?;
+        Ok(versions
+            .into_iter()
+            .map(|version| {
+                let dir = parent.join(&version);
+                (version, dir)
+            })
+            .collect())
+    }
+
+    /// Returns the `set` actions of the migrations that apply to updates
+    /// from `base_version`.
+    pub fn migration_actions(&self, base_version: &str) -> anyhow::Result<Vec<Action>> {
+        let mut actions = vec![];
+        for migration in &self.migrations {
+            if !migration.applies_to(base_version) {
+                continue;
+            }
+            for (setting, value) in &migration.settings {
+                settings::push_set(&mut actions, setting, value)
+                    .with_context(|| format!("Migrations from version {base_version}"))?;
+            }
+        }
+        Ok(actions)
+    }
+}
@@ -0,0 +1,128 @@
+use {
+    crate::release_manifest::{Constraints, parse_date},
+    std::{cmp::Ordering, fmt},
+};
+
+/// The state of a device a release is about to be installed on. Only the
+/// conditions for which the state is known are evaluated.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct DeviceState {
+    /// Version installed on the device.
+    #[arg(long = "device-version", value_name = "VERSION")]
+    pub version: Option<String>,
+    /// Hardware revision of the device.
+    #[arg(long = "device-hardware-revision", value_name = "REVISION")]
+    pub hardware_revision: Option<String>,
+    /// Bootloader version installed on the device.
+    #[arg(long = "device-bootloader-version", value_name = "VERSION")]
+    pub bootloader_version: Option<String>,
+    /// Current date on the device, as `YYYY-MM-DD`.
+    #[arg(long = "device-date", value_name = "DATE", value_parser = parse_date)]
+    pub date: Option<String>,
+}
+
+/// A release condition the device does not meet.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Violation {
+    FromVersion { version: String },
+    HardwareRevision { revision: String },
+    BootloaderVersion { version: String, min: String },
+    Expired { date: String, expires: String },
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Violation::FromVersion { version } => {
+                write!(f, "release can't be installed on version {version}")
+            }
+            Violation::HardwareRevision { revision } => {
+                write!(
+                    f,
+                    "release can't be installed on hardware revision {revision}"
+                )
+            }
+            Violation::BootloaderVersion { version, min } => {
+                write!(
+                    f,
+                    "release needs bootloader {min} or newer, device has {version}"
+                )
+            }
+            Violation::Expired { date, expires } => {
+                write!(f, "release expired on {expires}, device date is {date}")
+            }
+        }
+    }
+}
+
+/// Evaluates the release constraints against the device state.
+pub fn evaluate(constraints: &Constraints, device: &DeviceState) -> Vec<Violation> {
+    let mut violations = vec![];
+
+    if let Some(version) = &device.version
+        && !constraints.from_versions.is_empty()
+        && !constraints
+            .from_versions
+            .iter()
+            .any(|from| compare_versions(from, version) == Ordering::Equal)
+    {
+        violations.push(Violation::FromVersion {
+            version: version.clone(),
+        });
+    }
+    if let Some(revision) = &device.hardware_revision
+        && !constraints.hardware_revisions.is_empty()
+        && !constraints.hardware_revisions.contains(revision)
+    {
+        violations.push(Violation::HardwareRevision {
+            revision: revision.clone(),
+        });
+    }
+    if let (Some(version), Some(min)) = (
+        &device.bootloader_version,
+        &constraints.min_bootloader_version,
+    ) && compare_versions(version, min) == Ordering::Less
+    {
+        violations.push(Violation::BootloaderVersion {
+            version: version.clone(),
+            min: min.clone(),
+        });
+    }
+    // Dates are `YYYY-MM-DD`, so they compare correctly as strings.
+    if let (Some(date), Some(expires)) = (&device.date, &constraints.expires)
+        && date > expires
+    {
+        violations.push(Violation::Expired {
+            date: date.clone(),
+            expires: expires.clone(),
+        });
+    }
+
+    violations
+}
+
+/// Compares dot separated versions (e.g. `v1.0.2`) component by component,
+/// numerically where possible. The `v` prefix is ignored.
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let components = |version: &str| {
+        version
+            .trim_start_matches('v')
+            .split('.')
+            .map(str::to_string)
+            .collect::<Vec<_>>()
+    };
+    let (a, b) = (components(a), components(b));
+
+    for i in 0..a.len().max(b.len()) {
+        let a = a.get(i).map_or("0", String::as_str);
+        let b = b.get(i).map_or("0", String::as_str);
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+    Ordering::Equal
+}
@@ -0,0 +1,41 @@
+use {
+    std::path::{Path, PathBuf},
+    thiserror::Error,
+};
+
+/// Errors generating a release that callers may want to tell apart. Other
+/// failures are reported as plain [`anyhow`] errors with context.
+#[derive(Error, Debug)]
+pub enum GenerateError {
+    #[error("Path is not valid UTF-8: {}", .0.display())]
+    NonUtf8Path(PathBuf),
+
+    #[error(
+        "Tar file ({}) already exists. Please delete it before generating a new release.",
+        .0.display()
+    )]
+    TarExists(PathBuf),
+
+    #[error(
+        "Report file ({}) already exists. Please delete it before generating a new release.",
+        .0.display()
+    )]
+    ReportExists(PathBuf),
+
+    #[error("Source file of {} can't be read: {source}", .path.display())]
+    SourceUnreadable {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+
+    #[error("updiff failed for {}: {stderr}", .path.display())]
+    UpdiffFailed { path: PathBuf, stderr: String },
+}
+
+/// Converts a path relative to the root of a tree to the string used in the
+/// manifest.
+pub fn path_to_string(path: &Path) -> Result<String, GenerateError> {
+    path.to_str()
+        .map(str::to_string)
+        .ok_or_else(|| GenerateError::NonUtf8Path(path.to_path_buf()))
+}
@@ -0,0 +1,65 @@
+use {
+    anyhow::Context,
+    ignore::gitignore::{Gitignore, GitignoreBuilder},
+    std::path::Path,
+};
+
+/// Files left out of the release unless `--no-default-excludes` is given: the
+/// release config and notes, READMEs, and OS and editor clutter.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "/release-config.toml",
+    "/release-notes.*.md",
+    "README.md",
+    ".DS_Store",
+    "*~",
+    "*.swp",
+    "*.swo",
+    "*.bak",
+    "*.orig",
+    ".#*",
+    "#*#",
+];
+
+/// Decides which files of a tree go into the release, using gitignore
+/// patterns. Patterns are matched against paths relative to the root of the
+/// tree, and later patterns take precedence over earlier ones.
+#[derive(Debug)]
+pub struct TreeFilter {
+    gitignore: Gitignore,
+}
+
+impl TreeFilter {
+    /// A filter excluding nothing.
+    pub fn none() -> Self {
+        Self {
+            gitignore: Gitignore::empty(),
+        }
+    }
+
+    /// Excludes the files matching `excludes`, except for the ones matching
+    /// `includes`.
+    pub fn new<'a>(
+        excludes: impl IntoIterator<Item = &'a str>,
+        includes: impl IntoIterator<Item = &'a str>,
+    ) -> anyhow::Result<Self> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in excludes {
+            builder
+                .add_line(None, pattern)
+                .with_context(|| format!("Invalid exclude pattern: {pattern}"))?;
+        }
+        for pattern in includes {
+            builder
+                .add_line(None, &format!("!{pattern}"))
+                .with_context(|| format!("Invalid include pattern: {pattern}"))?;
+        }
+        let gitignore = builder.build().context("Building the tree filter")?;
+        Ok(Self { gitignore })
+    }
+
+    /// Whether the file or directory at `path`, relative to the root of the
+    /// tree, is left out.
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        self.gitignore.matched(path, is_dir).is_ignore()
+    }
+}
@@ -0,0 +1,218 @@
+use {
+    anyhow::Context,
+    std::{
+        ffi::OsStr,
+        fs::Permissions,
+        io::Write,
+        os::unix::{ffi::OsStrExt, fs::PermissionsExt},
+        path::{Path, PathBuf},
+        process::{self, Stdio},
+    },
+};
+
+/// Git refs to read the trees from instead of the file system.
+#[derive(clap::Args, Debug, Clone, Default)]
+pub struct GitRefs {
+    /// Read the base tree from this git ref (tag, branch or commit) of the
+    /// local repository. The base path is then the path of the tree inside of
+    /// the repository.
+    #[arg(long, value_name = "REF")]
+    pub base_ref: Option<String>,
+    /// Read the new tree from this git ref of the local repository. The new
+    /// path is then the path of the tree inside of the repository.
+    #[arg(long, value_name = "REF")]
+    pub new_ref: Option<String>,
+    /// Path to the git repository the refs are read from.
+    #[arg(long, value_name = "PATH", default_value = ".")]
+    pub repo: PathBuf,
+}
+
+/// A directory of a commit, extracted into a temporary directory that is
+/// removed when dropped.
+#[derive(Debug)]
+pub struct GitTree {
+    /// Full ID of the commit.
+    pub commit: String,
+    path: PathBuf,
+    _dir: tempfile::TempDir,
+}
+
+impl GitTree {
+    /// Extracts the directory `path` (relative to the root of the repository)
+    /// of the commit `reference` points to. Only the objects of the local
+    /// repository are used, nothing is fetched.
+    ///
+    /// The files are the blobs of the commit, byte for byte. Unlike with
+    /// `git archive` or a checkout, `.gitattributes` are not applied: files
+    /// marked `export-ignore` are extracted, `export-subst` placeholders are
+    /// not expanded and neither line endings nor filters are converted, so the
+    /// tree doesn't depend on the attributes of the commit or the local
+    /// configuration. Submodules are skipped.
+    pub fn extract(repo: &Path, reference: &str, path: &Path) -> anyhow::Result<Self> {
+        let commit = git(
+            repo,
+            &["rev-parse", "--verify", &format!("{reference}^{{commit}}")],
+        )
+        .with_context(|| format!("Resolving git ref {reference}"))?;
+        let commit = String::from_utf8(commit)
+            .context("Commit ID is not UTF-8")?
+            .trim()
+            .to_string();
+
+        let path_arg = path.to_str().context("Tree path is not UTF-8")?;
+        let listing = git(repo, &["ls-tree", "-r", "-z", &commit, "--", path_arg])
+            .with_context(|| format!("Reading {} at {reference}", path.display()))?;
+        let entries = listing
+            .split(|byte| *byte == 0)
+            .filter(|entry| !entry.is_empty())
+            .map(TreeEntry::parse)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .with_context(|| format!("Reading {} at {reference}", path.display()))?;
+        anyhow::ensure!(
+            !entries.is_empty(),
+            "{} does not exist at {reference}",
+            path.display()
+        );
+
+        let dir = tempfile::Builder::new()
+            .prefix(".release-gen-git-")
+            .tempdir()
+            .context("Creating temporary dir")?;
+        let blobs: Vec<_> = entries
+            .iter()
+            .filter(|entry| entry.mode != SUBMODULE_MODE)
+            .collect();
+        let objects: Vec<u8> = blobs
+            .iter()
+            .flat_map(|entry| [entry.object.as_bytes(), b"\n"].concat())
+            .collect();
+        let contents = git_with_input(repo, &["cat-file", "--batch"], objects)
+            .with_context(|| format!("Reading {} at {reference}", path.display()))?;
+        let mut contents = contents.as_slice();
+        for entry in blobs {
+            let blob = next_blob(&mut contents, &entry.object)
+                .with_context(|| format!("Reading {} at {reference}", entry.path.display()))?;
+            entry
+                .write(dir.path(), blob)
+                .with_context(|| format!("Extracting {} at {reference}", entry.path.display()))?;
+        }
+
+        Ok(Self {
+            commit,
+            path: dir.path().join(path),
+            _dir: dir,
+        })
+    }
+
+    /// Path of the extracted tree.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Mode of submodule entries in `git ls-tree`.
+const SUBMODULE_MODE: &str = "160000";
+
+/// A file of a commit, as listed by `git ls-tree -z`.
+struct TreeEntry {
+    mode: String,
+    object: String,
+    path: PathBuf,
+}
+
+impl TreeEntry {
+    /// Parses `<mode> <type> <object>\t<path>`.
+    fn parse(entry: &[u8]) -> anyhow::Result<Self> {
+        let tab = entry
+            .iter()
+            .position(|byte| *byte == b'\t')
+            .context("Unexpected git ls-tree output")?;
+        let info = std::str::from_utf8(&entry[..tab]).context("Unexpected git ls-tree output")?;
+        let [mode, _kind, object] = info.split(' ').collect::<Vec<_>>()[..] else {
+            anyhow::bail!("Unexpected git ls-tree output: {info}");
+        };
+        Ok(Self {
+            mode: mode.to_string(),
+            object: object.to_string(),
+            path: PathBuf::from(OsStr::from_bytes(&entry[tab + 1..])),
+        })
+    }
+
+    /// Writes the file with `contents` under `dir`.
+    fn write(&self, dir: &Path, contents: &[u8]) -> anyhow::Result<()> {
+        let path = dir.join(&self.path);
+        let parent = path.parent().expect("File should have a parent");
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Creating dir: {}", parent.display()))?;
+        let mode = match self.mode.as_str() {
+            "120000" => {
+                return std::os::unix::fs::symlink(OsStr::from_bytes(contents), &path)
+                    .with_context(|| format!("Creating link: {}", path.display()));
+            }
+            "100755" => 0o755,
+            "100644" => 0o644,
+            mode => anyhow::bail!("Unexpected mode {mode}"),
+        };
+        std::fs::write(&path, contents)
+            .with_context(|| format!("Writing file: {}", path.display()))?;
+        std::fs::set_permissions(&path, Permissions::from_mode(mode))
+            .with_context(|| format!("Setting the mode of: {}", path.display()))
+    }
+}
+
+/// Takes the contents of `object` from the front of the `git cat-file
+/// --batch` output.
+fn next_blob<'a>(output: &mut &'a [u8], object: &str) -> anyhow::Result<&'a [u8]> {
+    let newline = output
+        .iter()
+        .position(|byte| *byte == b'\n')
+        .context("Unexpected end of git cat-file output")?;
+    let header =
+        std::str::from_utf8(&output[..newline]).context("Unexpected git cat-file output")?;
+    let size = match header.split(' ').collect::<Vec<_>>()[..] {
+        [id, "blob", size] if id == object => size.parse::<usize>()?,
+        _ => anyhow::bail!("Unexpected git cat-file output for {object}: {header}"),
+    };
+    let rest = &output[newline + 1..];
+    anyhow::ensure!(
+        rest.len() > size && rest[size] == b'\n',
+        "Unexpected end of git cat-file output"
+    );
+    *output = &rest[size + 1..];
+    Ok(&rest[..size])
+}
+
+/// Runs `git` in `repo` and returns its output.
+fn git(repo: &Path, args: &[&str]) -> anyhow::Result<Vec<u8>> {
+    git_with_input(repo, args, vec![])
+}
+
+/// Runs `git` in `repo` with `input` on its stdin and returns its output.
+fn git_with_input(repo: &Path, args: &[&str], input: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+    let mut child = process::Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(args)
+        // Objects missing from a partial clone must not be fetched.
+        .env("GIT_NO_LAZY_FETCH", "1")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("Running git")?;
+    // Written from another thread, git may only read more input once its
+    // output has been read.
+    let mut stdin = child.stdin.take().expect("Stdin is piped");
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().context("Running git")?;
+    let written = writer.join().expect("Writing to git should not panic");
+    // A failing git may not read all of its input, its error comes first.
+    anyhow::ensure!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    written.context("Writing to git")?;
+    Ok(output.stdout)
+}
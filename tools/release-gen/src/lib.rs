@@ -0,0 +1,657 @@
+//! Generates KeyOS releases: tars with a `manifest.json` describing the
+//! actions that update a base tree to a new tree, and the patches and files
+//! the actions need.
+//!
+//! ```no_run
+//! use {
+//!     release_gen::{
+//!         ReleaseOptions,
+//!         archive::ReleaseArchive,
+//!         check::check_release,
+//!         release::ReleaseBuilder,
+//!         tree::TreeSnapshot,
+//!     },
+//!     std::path::Path,
+//! };
+//!
+//! let base = TreeSnapshot::new(Path::new("0.9.0"))?;
+//! let new = TreeSnapshot::new(Path::new("1.0.0"))?;
+//! let mut plan = ReleaseBuilder::new("0.9.0", &base, "1.0.0", &new)
+//!     .options(ReleaseOptions {
+//!         mandatory: true,
+//!         ..Default::default()
+//!     })
+//!     .plan()?;
+//! println!("{:?}", plan.actions());
+//! plan.write(Path::new("release.tar"))?;
+//! println!("{}", plan.build_report().timings);
+//!
+//! let release = ReleaseArchive::open(Path::new("release.tar"))?;
+//! assert!(check_release(&release).is_empty());
+//! # anyhow::Ok(())
+//! ```
+//!
+//! The `release-gen` binary is a command line interface to this crate.
+
+use {
+    anyhow::Context,
+    config::ReleaseConfig,
+    error::GenerateError,
+    filter::TreeFilter,
+    git::{GitRefs, GitTree},
+    globset::{Glob, GlobSet, GlobSetBuilder},
+    release::{BuildReport, ReleaseBuilder},
+    release_manifest::{
+        Action,
+        Commits,
+        Compression,
+        Constraints,
+        FORMAT_VERSION,
+        Label,
+        LocalizedText,
+    },
+    report::ReportFormat,
+    serde::{Deserialize, Serialize},
+    std::{
+        fs::File,
+        num::{NonZeroU64, NonZeroUsize},
+        path::{Path, PathBuf},
+        process,
+    },
+    tree::TreeSnapshot,
+};
+
+pub mod apply;
+mod apps;
+pub mod archive;
+pub mod check;
+pub mod compose;
+pub mod compression;
+pub mod config;
+pub mod constraints;
+pub mod error;
+pub mod filter;
+pub mod git;
+pub mod patch_cache;
+mod patcher;
+pub mod release;
+pub mod release_manifest;
+pub mod report;
+pub mod schema;
+mod settings;
+#[cfg(test)]
+mod test;
+pub mod timing;
+pub mod tree;
+
+/// Options shared by all ways of generating releases.
+#[derive(Debug, Clone)]
+pub struct ReleaseOptions {
+    /// Label of the release shown to the user. Overrides the labels from the
+    /// release notes.
+    pub label: Option<String>,
+    /// Label of the release per language, used when there is no `label`.
+    pub localized_label: LocalizedText,
+    /// Release notes per language.
+    pub notes: LocalizedText,
+    /// Mark the release as mandatory.
+    pub mandatory: bool,
+    /// Path to the `updiff` tool binary.
+    pub updiff_path: PathBuf,
+    /// Format version of the generated `manifest.json`. Use an older version
+    /// for devices running firmware that can't parse the newest one.
+    pub format_version: u32,
+    /// Conditions the device has to meet to install the release. Unset
+    /// conditions are taken from the `[constraints]` table of the
+    /// `release-config.toml` in the new directory.
+    pub constraints: Constraints,
+    /// Glob patterns matching the Bluetooth controller firmware in the tree,
+    /// relative to its root. An `update-bt` action is added when a matching
+    /// file is added or changed. Defaults to the `[bluetooth]` table of the
+    /// `release-config.toml` in the new directory.
+    pub bt_firmware: Vec<String>,
+    /// `appId` of the app opened after the update, e.g. to show what's new.
+    /// The app has to be in the new tree. Defaults to the `[open-app]` table
+    /// of the `release-config.toml` in the new directory.
+    pub open_app: Option<String>,
+    /// Route the `open_app` is opened at. Needed with `open_app`.
+    pub open_app_route: Option<String>,
+    /// Gitignore-style patterns of files left out of the release, relative to
+    /// the root of the trees. Added after the `[files]` patterns of the
+    /// `release-config.toml` in the new directory.
+    pub exclude: Vec<String>,
+    /// Gitignore-style patterns of files put into the release even if they
+    /// match an exclude pattern.
+    pub include: Vec<String>,
+    /// Don't leave out the release config and notes, READMEs, and OS and
+    /// editor clutter by default.
+    pub no_default_excludes: bool,
+    /// What to do with symbolic links and executable bits that differ between
+    /// the trees. Defaults to the `[files]` table of the
+    /// `release-config.toml` in the new directory, or `fail`.
+    pub metadata_policy: Option<MetadataPolicy>,
+    /// Number of `updiff` processes run at the same time. Defaults to the
+    /// number of CPUs.
+    pub jobs: Option<NonZeroUsize>,
+    /// Directory where patches are cached across runs. A patch is reused
+    /// when the same base and new file are diffed again for the same
+    /// versions.
+    pub patch_cache: Option<PathBuf>,
+    /// Compress the patches and added files with this codec. Payloads that
+    /// don't get smaller are stored as is. Needs format version 6 or newer.
+    pub compression: Option<Compression>,
+    /// Patch files larger than this in windows of this size, so the device
+    /// only needs one window in memory. Needs format version 7 or newer.
+    pub chunk_size: Option<NonZeroU64>,
+    /// Reports of the actions and their sizes written next to the release
+    /// tar, as `<tar name>.md` or `<tar name>.json`.
+    pub report: Vec<ReportFormat>,
+    /// Also generate a rollback release from the new version back to the
+    /// base version, next to the release tar as `<tar name>-rollback.tar`.
+    pub with_rollback: bool,
+    /// Whether the release is a rollback.
+    pub is_rollback: bool,
+    /// Commits the trees were read from.
+    pub commits: Commits,
+    /// Date of the release, as `YYYY-MM-DD`. Defaults to the date of
+    /// `SOURCE_DATE_EPOCH` if set, or today (UTC). The entries of the release
+    /// tar are timestamped with the same time.
+    pub date: Option<String>,
+}
+
+/// What to do with symbolic links and file modes that differ between the
+/// base and the new tree.
+#[derive(clap::ValueEnum, Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum MetadataPolicy {
+    /// Fail, listing the affected paths.
+    #[default]
+    Fail,
+    /// Add `symlink` and `set-mode` actions (format version 5 and newer).
+    Actions,
+}
+
+/// Oldest format version that can mark a release as a rollback.
+const ROLLBACK_FORMAT_VERSION: u32 = 8;
+
+/// Label used when there is neither `--label` nor any release notes.
+const DEFAULT_LABEL: &str = "KeyOS Release";
+
+impl Default for ReleaseOptions {
+    /// The options of `release-gen` without any flags.
+    fn default() -> Self {
+        Self {
+            label: None,
+            localized_label: LocalizedText::new(),
+            notes: LocalizedText::new(),
+            mandatory: false,
+            updiff_path: PathBuf::from("updiff"),
+            format_version: FORMAT_VERSION,
+            constraints: Constraints::default(),
+            bt_firmware: vec![],
+            open_app: None,
+            open_app_route: None,
+            exclude: vec![],
+            include: vec![],
+            no_default_excludes: false,
+            metadata_policy: None,
+            jobs: None,
+            patch_cache: None,
+            compression: None,
+            chunk_size: None,
+            report: vec![],
+            with_rollback: false,
+            is_rollback: false,
+            commits: Commits::default(),
+            date: None,
+        }
+    }
+}
+
+impl ReleaseOptions {
+    /// Fills the options not given on the command line from the release
+    /// config.
+    pub fn with_config(mut self, config: Option<&ReleaseConfig>) -> Self {
+        if let Some(config) = config {
+            self.constraints = self.constraints.or(&config.constraints);
+            if self.localized_label.is_empty() {
+                self.localized_label.clone_from(&config.notes.labels);
+            }
+            if self.notes.is_empty() {
+                self.notes.clone_from(&config.notes.notes);
+            }
+            if self.bt_firmware.is_empty() {
+                self.bt_firmware.clone_from(&config.bluetooth.firmware);
+            }
+            self.metadata_policy = self.metadata_policy.or(config.files.metadata_policy);
+            self.exclude
+                .splice(0..0, config.files.exclude.iter().cloned());
+            self.include
+                .splice(0..0, config.files.include.iter().cloned());
+            if self.open_app.is_none()
+                && let Some(open_app) = &config.open_app
+            {
+                self.open_app = Some(open_app.app_id.clone());
+                self.open_app_route = Some(open_app.route.clone());
+            }
+        }
+        self
+    }
+
+    fn manifest_label(&self) -> Label {
+        match &self.label {
+            Some(label) => Label::Text(label.clone()),
+            None if !self.localized_label.is_empty() => {
+                Label::Localized(self.localized_label.clone())
+            }
+            None => Label::Text(DEFAULT_LABEL.to_string()),
+        }
+    }
+
+    /// Filter of the files that go into the release.
+    pub fn tree_filter(&self) -> anyhow::Result<TreeFilter> {
+        let defaults = if self.no_default_excludes {
+            &[][..]
+        } else {
+            filter::DEFAULT_EXCLUDES
+        };
+        TreeFilter::new(
+            defaults
+                .iter()
+                .copied()
+                .chain(self.exclude.iter().map(String::as_str)),
+            self.include.iter().map(String::as_str),
+        )
+    }
+
+    /// Fails early on options the rollback can't be generated with, before
+    /// the forward release is written.
+    fn check_rollback(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            !self.with_rollback || self.format_version >= ROLLBACK_FORMAT_VERSION,
+            "Rollbacks need format version {ROLLBACK_FORMAT_VERSION} or newer"
+        );
+        Ok(())
+    }
+
+    /// Options of the rollback from `new_version` back to `base_version`.
+    ///
+    /// The rollback can only be installed on `new_version` and doesn't carry
+    /// the release notes, the settings migrations or the app to open, which
+    /// are about the update.
+    fn rollback_options(&self, base_version: &str, new_version: &str) -> Self {
+        Self {
+            label: Some(format!("Rollback to {base_version}")),
+            localized_label: LocalizedText::new(),
+            notes: LocalizedText::new(),
+            mandatory: false,
+            constraints: Constraints {
+                from_versions: vec![new_version.to_string()],
+                ..self.constraints.clone()
+            },
+            open_app: None,
+            open_app_route: None,
+            with_rollback: false,
+            is_rollback: true,
+            commits: Commits {
+                base: self.commits.new.clone(),
+                new: self.commits.base.clone(),
+            },
+            ..self.clone()
+        }
+    }
+
+    fn open_app_action(&self) -> Option<Action> {
+        Some(Action::OpenApp {
+            app_id: self.open_app.clone()?,
+            route: self.open_app_route.clone()?,
+        })
+    }
+
+    fn bt_firmware_matcher(&self) -> anyhow::Result<GlobSet> {
+        let mut builder = GlobSetBuilder::new();
+        for pattern in &self.bt_firmware {
+            builder.add(
+                Glob::new(pattern)
+                    .with_context(|| format!("Invalid Bluetooth firmware pattern: {pattern}"))?,
+            );
+        }
+        builder
+            .build()
+            .context("Building the Bluetooth firmware patterns")
+    }
+}
+
+/// A release from the tree at `base` to the tree at `new`, see [`generate`].
+#[derive(Debug, Clone)]
+pub struct GenerateRequest {
+    /// Version before the update.
+    pub base_version: String,
+    /// Path to the base directory.
+    pub base: PathBuf,
+    /// Version after the update.
+    pub new_version: String,
+    /// Path to the new directory.
+    pub new: PathBuf,
+    /// Git refs to read the trees from instead of the file system.
+    pub git: GitRefs,
+    pub options: ReleaseOptions,
+    /// Path where the release tar should be created. The directory does not
+    /// need to exist, it will be created if missing.
+    pub out: PathBuf,
+}
+
+/// Releases from several base versions to the same new version, see
+/// [`fan_out`].
+#[derive(Debug, Clone)]
+pub struct FanOutRequest {
+    /// Version after the update.
+    pub new_version: String,
+    /// Path to the new directory.
+    pub new: PathBuf,
+    /// Base versions and the paths to their directories.
+    ///
+    /// If empty, `base-versions` (or `base-version`) is read from the
+    /// `release-config.toml` in the new directory and every base version is
+    /// expected to be a sibling folder of the new directory.
+    pub bases: Vec<(String, PathBuf)>,
+    pub options: ReleaseOptions,
+    /// Directory where the release tars and `index.json` should be created.
+    /// The directory does not need to exist, it will be created if missing.
+    pub out_dir: PathBuf,
+}
+
+/// Name of the index file written by [`fan_out`].
+const INDEX_FILE: &str = "index.json";
+
+/// Contents of the `index.json` written by [`fan_out`].
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReleaseIndex {
+    pub new_version: String,
+    pub releases: Vec<ReleaseIndexEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReleaseIndexEntry {
+    pub base_version: String,
+    /// Path of the release tar, relative to the index file.
+    pub file: String,
+    /// Hex encoded SHA-256 of the release tar.
+    pub sha256: String,
+    /// The rollback release from the new version back to the base version,
+    /// if generated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rollback: Option<ReleaseIndexFile>,
+}
+
+/// A release tar listed in `index.json`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct ReleaseIndexFile {
+    /// Path of the release tar, relative to the index file.
+    pub file: String,
+    /// Hex encoded SHA-256 of the release tar.
+    pub sha256: String,
+}
+
+impl ReleaseIndexFile {
+    fn new(path: &Path) -> anyhow::Result<Self> {
+        let (_, sha256) = tree::hash_file(path)?;
+        Ok(Self {
+            file: path
+                .file_name()
+                .context("Release tar has no file name")?
+                .to_string_lossy()
+                .into_owned(),
+            sha256: hex::encode(sha256),
+        })
+    }
+}
+
+/// Generates the release tar of the request and, if the options ask for it,
+/// its rollback. Returns how it went.
+pub fn generate(request: GenerateRequest) -> anyhow::Result<BuildReport> {
+    check_updiff(&request.options.updiff_path)?;
+
+    let extract = |reference: &Option<String>, path| {
+        reference
+            .as_deref()
+            .map(|reference| GitTree::extract(&request.git.repo, reference, path))
+            .transpose()
+    };
+    let base_tree = extract(&request.git.base_ref, &request.base)?;
+    let new_tree = extract(&request.git.new_ref, &request.new)?;
+    let base_dir = base_tree
+        .as_ref()
+        .map_or(request.base.as_path(), GitTree::path);
+    let new_dir = new_tree
+        .as_ref()
+        .map_or(request.new.as_path(), GitTree::path);
+
+    let config = ReleaseConfig::load_optional(new_dir)?;
+    let mut options = request.options.with_config(config.as_ref());
+    options.check_rollback()?;
+    options.commits = Commits {
+        base: base_tree.as_ref().map(|tree| tree.commit.clone()),
+        new: new_tree.as_ref().map(|tree| tree.commit.clone()),
+    };
+    let filter = options.tree_filter()?;
+    let mut report = BuildReport::default();
+    let base = report
+        .timings
+        .time("snapshot", || TreeSnapshot::filtered(base_dir, &filter))
+        .context("Reading base dir")?;
+    let new = report
+        .timings
+        .time("snapshot", || TreeSnapshot::filtered(new_dir, &filter))
+        .context("Reading new dir")?;
+    let migrations = match &config {
+        Some(config) => config.migration_actions(&request.base_version)?,
+        None => vec![],
+    };
+
+    report.add(generate_release(
+        &request.base_version,
+        &base,
+        &request.new_version,
+        &new,
+        &options,
+        migrations,
+        &request.out,
+    )?);
+    if options.with_rollback {
+        report.add(
+            generate_release(
+                &request.new_version,
+                &new,
+                &request.base_version,
+                &base,
+                &options.rollback_options(&request.base_version, &request.new_version),
+                vec![],
+                &rollback_path(&request.out),
+            )
+            .context("Generating rollback release")?,
+        );
+    }
+    Ok(report)
+}
+
+/// Path of the rollback release of the release tar at `out`.
+pub fn rollback_path(out: &Path) -> PathBuf {
+    let stem = out.file_stem().unwrap_or_default().to_string_lossy();
+    out.with_file_name(format!("{stem}-rollback.tar"))
+}
+
+/// Generates a release from every base version to the new version, and an
+/// `index.json` listing which tar applies to which base version. The new
+/// tree is read and hashed only once.
+pub fn fan_out(request: FanOutRequest) -> anyhow::Result<BuildReport> {
+    check_updiff(&request.options.updiff_path)?;
+
+    let config = ReleaseConfig::load_optional(&request.new)?;
+    let options = request.options.with_config(config.as_ref());
+    options.check_rollback()?;
+    let bases = if request.bases.is_empty() {
+        let config = config.as_ref().with_context(|| {
+            format!(
+                "No base versions given and no {} in {}",
+                config::RELEASE_CONFIG_FILE,
+                request.new.display()
+            )
+        })?;
+        anyhow::ensure!(
+            config.release.version.trim_start_matches('v')
+                == request.new_version.trim_start_matches('v'),
+            "New version {} does not match the version in the release config ({})",
+            request.new_version,
+            config.release.version
+        );
+        config.bases(&request.new)?
+    } else {
+        request.bases
+    };
+
+    let filter = options.tree_filter()?;
+    let mut report = BuildReport::default();
+    let new = report
+        .timings
+        .time("snapshot", || TreeSnapshot::filtered(&request.new, &filter))
+        .context("Reading new dir")?;
+    let mut index = ReleaseIndex {
+        new_version: request.new_version.clone(),
+        releases: vec![],
+    };
+
+    for (base_version, base_dir) in bases {
+        let base = report
+            .timings
+            .time("snapshot", || TreeSnapshot::filtered(&base_dir, &filter))
+            .with_context(|| format!("Reading base dir for version {base_version}"))?;
+        let out = request.out_dir.join(format!(
+            "release-{}-{}.tar",
+            base_version, request.new_version
+        ));
+        let migrations = match &config {
+            Some(config) => config.migration_actions(&base_version)?,
+            None => vec![],
+        };
+
+        report.add(
+            generate_release(
+                &base_version,
+                &base,
+                &request.new_version,
+                &new,
+                &options,
+                migrations,
+                &out,
+            )
+            .with_context(|| format!("Generating release from version {base_version}"))?,
+        );
+
+        let rollback = if options.with_rollback {
+            let rollback_out = rollback_path(&out);
+            report.add(
+                generate_release(
+                    &request.new_version,
+                    &new,
+                    &base_version,
+                    &base,
+                    &options.rollback_options(&base_version, &request.new_version),
+                    vec![],
+                    &rollback_out,
+                )
+                .with_context(|| {
+                    format!("Generating rollback release to version {base_version}")
+                })?,
+            );
+            Some(ReleaseIndexFile::new(&rollback_out)?)
+        } else {
+            None
+        };
+
+        let ReleaseIndexFile { file, sha256 } = ReleaseIndexFile::new(&out)?;
+        index.releases.push(ReleaseIndexEntry {
+            base_version,
+            file,
+            sha256,
+            rollback,
+        });
+    }
+
+    let index_path = request.out_dir.join(INDEX_FILE);
+    let index_file = File::create_new(&index_path)
+        .with_context(|| format!("Creating index file: {}", index_path.display()))?;
+    serde_json::to_writer_pretty(index_file, &index)
+        .with_context(|| format!("Writing index file: {}", index_path.display()))?;
+
+    Ok(report)
+}
+
+pub(crate) fn check_updiff(updiff_path: &Path) -> anyhow::Result<()> {
+    if let Err(err) = process::Command::new(updiff_path.as_os_str()).output()
+        && err.to_string().contains("No such file or directory")
+    {
+        anyhow::bail!(
+            r"updiff tool not found at {}
+Please make sure it's in your PATH or specify the path where it is installed. See `--help` for more information.",
+            updiff_path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Creates the release tar at `out` that updates `base` to `new`, with the
+/// reports of the options next to it. See [`ReleaseBuilder`].
+pub fn generate_release(
+    base_version: &str,
+    base: &TreeSnapshot,
+    new_version: &str,
+    new: &TreeSnapshot,
+    options: &ReleaseOptions,
+    device_actions: Vec<Action>,
+    out: &Path,
+) -> anyhow::Result<BuildReport> {
+    // Checked first so that no work is wasted on a release that can't be
+    // written.
+    if out.try_exists().unwrap_or(true) {
+        return Err(GenerateError::TarExists(out.to_path_buf()).into());
+    }
+    for format in &options.report {
+        let path = format.path(out);
+        if path.try_exists().unwrap_or(true) {
+            return Err(GenerateError::ReportExists(path).into());
+        }
+    }
+    // The staging dir is next to the tar, on the same file system.
+    let out_dir = release::out_dir(out);
+    std::fs::create_dir_all(out_dir)
+        .with_context(|| format!("Creating output dir: {}", out_dir.display()))?;
+
+    let mut plan = ReleaseBuilder::new(base_version, base, new_version, new)
+        .options(options.clone())
+        .device_actions(device_actions)
+        .staging_dir(out_dir)
+        .plan()?;
+    plan.write(out)?;
+
+    if !options.report.is_empty() {
+        let download_size = std::fs::metadata(out)
+            .with_context(|| format!("Reading tar file: {}", out.display()))?
+            .len();
+        let report = plan.report(download_size)?;
+        for format in &options.report {
+            report.write(*format, out)?;
+        }
+    }
+    Ok(plan.into_build_report())
+}
+
+pub(crate) fn create_parent(path: &Path) -> anyhow::Result<()> {
+    let Some(parent) = path.parent() else {
+        return Ok(());
+    };
+    std::fs::create_dir_all(parent).with_context(|| format!("Creating dir: {}", parent.display()))
+}
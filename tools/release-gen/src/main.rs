@@ -1,293 +1,745 @@
 use {
     anyhow::Context,
-    clap::Parser,
-    release_manifest::{Action, ReleaseManifest},
+    clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, error::ErrorKind},
+    release_gen::{
+        FanOutRequest,
+        GenerateRequest,
+        MetadataPolicy,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::{Issue, check_release, check_release_with_settings},
+        compose::{ComposeRequest, compose},
+        config::ReleaseConfig,
+        constraints::{self, DeviceState},
+        fan_out,
+        generate,
+        git::GitRefs,
+        patch_cache::{CacheContents, PATCH_FORMAT_VERSION, PruneFilter, prune},
+        release::BuildReport,
+        release_manifest::{
+            Compression,
+            Constraints,
+            FORMAT_VERSION,
+            MIN_FORMAT_VERSION,
+            parse_date,
+        },
+        report::ReportFormat,
+        schema::{release_manifest_schema, validate_manifest},
+        tree::TreeSnapshot,
+    },
     std::{
-        fs::{File, ReadDir},
-        io::{Read, Write},
-        path::{Path, PathBuf},
-        process::Command,
+        num::{NonZeroU64, NonZeroUsize},
+        path::PathBuf,
+        time::Duration,
     },
 };
 
-mod release_manifest;
-#[cfg(test)]
-mod test;
-
-const PATH_TO_STR_ERROR: &str = "Path should be a valid string";
-
-/// `release-gen` traverses the two directories and crates a `release.tar` file
-/// that contains the manifest describing what actions to perform to reach the
-/// destination directory state starting from the source one.
+/// `release-gen` traverses the two directories and creates a `release.tar`
+/// file that contains the manifest describing what actions to perform to
+/// reach the destination directory state starting from the source one.
+///
+/// Both trees are traversed in sorted order and the generated transaction
+/// always lists its actions in the same order: deletes, then renames, then
+/// patches (whole or chunked), then adds and links, then mode changes, then
+/// `update-bt` and the `set` actions of the migrations, with `open-app`
+/// after the transaction. The same two trees always produce the same
+/// `release.tar`.
 ///
 /// Uses the `updiff` tool. See: https://github.com/Foundation-Devices/updiff
 #[derive(Parser, Debug)]
-pub struct Args {
+#[command(
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true,
+    arg_required_else_help = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    args: Option<Args>,
+}
+
+impl Cli {
+    /// Parses the command line, exiting on errors.
+    fn parse_args() -> Self {
+        Self::from_matches(&Self::command().get_matches()).unwrap_or_else(|err| err.exit())
+    }
+
+    /// Clap can't tell whether the flattened `Option<Args>` is present when
+    /// `Args` itself flattens other args, so the matches are converted by
+    /// hand.
+    fn from_matches(matches: &ArgMatches) -> Result<Self, clap::Error> {
+        if matches.subcommand().is_some() {
+            Ok(Self {
+                command: Some(Command::from_arg_matches(matches)?),
+                args: None,
+            })
+        } else {
+            Ok(Self {
+                command: None,
+                args: Some(Args::from_arg_matches(matches)?),
+            })
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Generate one release tar per base version, all leading to the same new
+    /// version, and an `index.json` listing which tar applies to which base
+    /// version.
+    FanOut(Box<FanOutArgs>),
+    /// Compose two or more chained releases into a single release going
+    /// directly from the first base version to the last new version.
+    Compose(ComposeArgs),
+    /// Apply a release to a copy of the base directory.
+    Apply(ApplyArgs),
+    /// Check that a release tar is well-formed.
+    Check(CheckArgs),
+    /// Print or validate against the JSON Schema of the release
+    /// `manifest.json`.
+    Schema(SchemaArgs),
+    /// Inspect, prune or verify a patch cache.
+    Cache(CacheArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct Args {
     /// Version before the update.
-    pub base_version: String,
+    base_version: String,
     /// Path to the base directory.
-    pub base: PathBuf,
+    base: PathBuf,
     /// Version after the update.
-    pub new_version: String,
-    /// Path to the new directory.
-    pub new: PathBuf,
+    new_version: String,
     /// Path to the new directory.
-    #[arg(long, default_value = "KeyOS Release")]
-    pub label: String,
-    /// Path to the new directory.
-    #[arg(long)]
-    pub mandatory: bool,
+    new: PathBuf,
+    #[command(flatten)]
+    git: GitRefs,
+    #[command(flatten)]
+    options: ReleaseArgs,
     /// Path where the release tar (output of `release-gen`) should be created.
     /// The directory does not need to exist, it will be created if missing.
     ///
     /// Example: ./out/release.tar
     #[arg(short, long, default_value = "release.tar")]
-    pub out: PathBuf,
+    out: PathBuf,
+}
+
+/// Options shared by all commands generating releases.
+#[derive(clap::Args, Debug)]
+struct ReleaseArgs {
+    /// Label of the release shown to the user. Overrides the labels from the
+    /// release notes.
+    #[arg(long)]
+    label: Option<String>,
+    /// Mark the release as mandatory.
+    #[arg(long)]
+    mandatory: bool,
     /// Path to the `updiff` tool binary. If not specified, it is assumed that
     /// `updiff` is accessible from CWD.
     #[arg(long, default_value = "updiff")]
-    pub updiff_path: PathBuf,
+    updiff_path: PathBuf,
+    /// Format version of the generated `manifest.json`. Use an older version
+    /// for devices running firmware that can't parse the newest one.
+    #[arg(long, default_value_t = FORMAT_VERSION, value_parser = parse_format_version)]
+    format_version: u32,
+    /// Conditions the device has to meet to install the release. Unset
+    /// conditions are taken from the `[constraints]` table of the
+    /// `release-config.toml` in the new directory.
+    #[command(flatten)]
+    constraints: Constraints,
+    /// Glob pattern matching the Bluetooth controller firmware in the tree,
+    /// relative to its root. An `update-bt` action is added when a matching
+    /// file is added or changed. Can be given several times. Defaults to the
+    /// `[bluetooth]` table of the `release-config.toml` in the new directory.
+    #[arg(long = "bt-firmware", value_name = "PATTERN")]
+    bt_firmware: Vec<String>,
+    /// `appId` of the app opened after the update, e.g. to show what's new.
+    /// The app has to be in the new tree. Defaults to the `[open-app]` table
+    /// of the `release-config.toml` in the new directory.
+    #[arg(long, value_name = "APP_ID", requires = "open_app_route")]
+    open_app: Option<String>,
+    /// Route the app given with `--open-app` is opened at.
+    #[arg(long, value_name = "ROUTE", requires = "open_app")]
+    open_app_route: Option<String>,
+    /// Gitignore-style pattern of files left out of the release, relative to
+    /// the root of the trees. Can be given several times. Added after the
+    /// `[files]` patterns of the `release-config.toml` in the new directory.
+    #[arg(long, value_name = "PATTERN")]
+    exclude: Vec<String>,
+    /// Gitignore-style pattern of files put into the release even if they
+    /// match an exclude pattern. Can be given several times.
+    #[arg(long, value_name = "PATTERN")]
+    include: Vec<String>,
+    /// Don't leave out the release config and notes, READMEs, and OS and
+    /// editor clutter by default.
+    #[arg(long)]
+    no_default_excludes: bool,
+    /// What to do with symbolic links and executable bits that differ between
+    /// the trees. Defaults to the `[files]` table of the
+    /// `release-config.toml` in the new directory, or `fail`.
+    #[arg(long, value_enum)]
+    metadata_policy: Option<MetadataPolicy>,
+    /// Number of `updiff` processes run at the same time. Defaults to the
+    /// number of CPUs.
+    #[arg(long, short = 'j', value_name = "N")]
+    jobs: Option<NonZeroUsize>,
+    /// Directory where patches are cached across runs. A patch is reused
+    /// when the same base and new file are diffed again for the same
+    /// versions.
+    #[arg(long, value_name = "DIR")]
+    patch_cache: Option<PathBuf>,
+    /// Compress the patches and added files with this codec. Payloads that
+    /// don't get smaller are stored as is. Needs format version 6 or newer.
+    #[arg(long, value_enum, value_name = "CODEC")]
+    compression: Option<Compression>,
+    /// Patch files larger than this in windows of this size, e.g. `256K`, so
+    /// the device only needs one window in memory. Needs format version 7 or
+    /// newer.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    chunk_size: Option<NonZeroU64>,
+    /// Write a report of the actions and their sizes next to the release
+    /// tar, as `<tar name>.md` or `<tar name>.json`. Can be given several
+    /// times.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Vec<ReportFormat>,
+    /// Also generate a rollback release from the new version back to the
+    /// base version, next to the release tar as `<tar name>-rollback.tar`.
+    #[arg(long)]
+    with_rollback: bool,
+    /// Date of the release, as `YYYY-MM-DD`. Defaults to the date of
+    /// `SOURCE_DATE_EPOCH` if set, or today (UTC). The entries of the release
+    /// tar are timestamped with the same time.
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_date)]
+    date: Option<String>,
 }
 
-fn main() -> anyhow::Result<()> {
-    let args = Args::parse();
-    run(args)
+impl From<ReleaseArgs> for ReleaseOptions {
+    fn from(args: ReleaseArgs) -> Self {
+        Self {
+            label: args.label,
+            mandatory: args.mandatory,
+            updiff_path: args.updiff_path,
+            format_version: args.format_version,
+            constraints: args.constraints,
+            bt_firmware: args.bt_firmware,
+            open_app: args.open_app,
+            open_app_route: args.open_app_route,
+            exclude: args.exclude,
+            include: args.include,
+            no_default_excludes: args.no_default_excludes,
+            metadata_policy: args.metadata_policy,
+            jobs: args.jobs,
+            patch_cache: args.patch_cache,
+            compression: args.compression,
+            chunk_size: args.chunk_size,
+            report: args.report,
+            with_rollback: args.with_rollback,
+            date: args.date,
+            ..Default::default()
+        }
+    }
 }
 
-pub fn run(args: Args) -> anyhow::Result<()> {
-    if let Err(err) = Command::new(args.updiff_path.as_os_str()).output() {
-        if err.to_string().contains("No such file or directory") {
-            anyhow::bail!(
-                r"updiff tool not found at {}
-Please make sure it's in your PATH or specify the path where it is installed. See `--help` for more information.",
-                args.updiff_path.display()
-            );
+#[derive(clap::Args, Debug)]
+struct FanOutArgs {
+    /// Version after the update.
+    new_version: String,
+    /// Path to the new directory.
+    new: PathBuf,
+    /// Base version and the path to its directory, as `VERSION=PATH`. Can be
+    /// repeated.
+    ///
+    /// If not specified, `base-versions` (or `base-version`) is read from the
+    /// `release-config.toml` in the new directory and every base version is
+    /// expected to be a sibling folder of the new directory.
+    #[arg(long = "base", value_name = "VERSION=PATH", value_parser = parse_base)]
+    bases: Vec<(String, PathBuf)>,
+    #[command(flatten)]
+    options: ReleaseArgs,
+    /// Directory where the release tars and `index.json` should be created.
+    /// The directory does not need to exist, it will be created if missing.
+    #[arg(short, long, default_value = "releases")]
+    out_dir: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct ComposeArgs {
+    /// Path to the directory of the version the first release updates from.
+    /// The intermediate trees are reconstructed from it.
+    #[arg(long)]
+    base: PathBuf,
+    /// Release tars to compose, in the order they would be applied.
+    #[arg(required = true, num_args = 2..)]
+    releases: Vec<PathBuf>,
+    /// Version before the update. Defaults to the base version of the first
+    /// release.
+    #[arg(long)]
+    base_version: Option<String>,
+    /// Version after the update. Defaults to the new version of the last
+    /// release.
+    #[arg(long)]
+    new_version: Option<String>,
+    /// Label of the composed release. Defaults to the label of the last
+    /// release. The release notes are always taken from the last release.
+    #[arg(long)]
+    label: Option<String>,
+    /// Path where the composed release tar should be created.
+    #[arg(short, long, default_value = "release.tar")]
+    out: PathBuf,
+    /// Path to the `updiff` tool binary. If not specified, it is assumed that
+    /// `updiff` is accessible from CWD.
+    #[arg(long, default_value = "updiff")]
+    updiff_path: PathBuf,
+    /// Format version of the composed `manifest.json`.
+    #[arg(long, default_value_t = FORMAT_VERSION, value_parser = parse_format_version)]
+    format_version: u32,
+    /// Directory where patches are cached across runs.
+    #[arg(long, value_name = "DIR")]
+    patch_cache: Option<PathBuf>,
+    /// Compress the payloads of the composed release with this codec.
+    #[arg(long, value_enum, value_name = "CODEC")]
+    compression: Option<Compression>,
+    /// Patch files larger than this in windows of this size.
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    chunk_size: Option<NonZeroU64>,
+    /// Write a report of the composed release next to it.
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    report: Vec<ReportFormat>,
+    /// Date of the composed release, as `YYYY-MM-DD`.
+    #[arg(long, value_name = "YYYY-MM-DD", value_parser = parse_date)]
+    date: Option<String>,
+}
+
+impl From<ComposeArgs> for ComposeRequest {
+    fn from(args: ComposeArgs) -> Self {
+        Self {
+            base: args.base,
+            releases: args.releases,
+            base_version: args.base_version,
+            new_version: args.new_version,
+            label: args.label,
+            out: args.out,
+            updiff_path: args.updiff_path,
+            format_version: args.format_version,
+            patch_cache: args.patch_cache,
+            compression: args.compression,
+            chunk_size: args.chunk_size,
+            report: args.report,
+            date: args.date,
         }
     }
+}
 
-    let mut out_path = args.out.clone();
-    // Remove the file name.
-    out_path.pop();
-    std::fs::create_dir_all(&out_path)
-        .with_context(|| format!("Creating output dir: {}", out_path.display()))?;
-    let Ok(tar_file) = File::create_new(&args.out) else {
-        anyhow::bail!(
-            "Tar file ({}) already exists. Please delete it before generating a new release.",
-            args.out.display()
-        );
-    };
-
-    let base_src_root = std::fs::read_dir(&args.base)
-        .with_context(|| format!("Reading base dir: {}", args.base.display()))?;
-    let new_src_root = std::fs::read_dir(&args.new)
-        .with_context(|| format!("Reading new dir: {}", args.new.display()))?;
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+    /// Path to the release tar.
+    release: PathBuf,
+    /// Path to the base directory the release applies to. It is not modified.
+    base: PathBuf,
+    /// Path where the updated directory should be created. Must not exist.
+    out_dir: PathBuf,
+    /// The device the release is installed on. The release is only applied if
+    /// the device meets the release constraints.
+    #[command(flatten)]
+    device: DeviceState,
+}
 
-    let out_patch_dir = out_path.join("patch");
-    let manifest_file_path = out_path.clone().join("manifest.json");
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    /// Path to the release tar.
+    release: PathBuf,
+    /// The device the release would be installed on. The release constraints
+    /// are evaluated against the given device state.
+    #[command(flatten)]
+    device: DeviceState,
+    /// Version folder whose `release-config.toml` lists the settings KeyOS
+    /// knows about in `[settings] known`. Without it, the names of the
+    /// settings changed by `set` actions are not checked.
+    #[arg(long, value_name = "DIR")]
+    config_dir: Option<PathBuf>,
+}
 
-    std::fs::create_dir(&out_patch_dir)
-        .with_context(|| format!("Creating patch dir: {}", out_patch_dir.display()))?;
-    let mut manifest_file =
-        File::create_new(&manifest_file_path).expect("Manifest file should not exist");
+#[derive(clap::Args, Debug)]
+struct SchemaArgs {
+    #[command(subcommand)]
+    command: SchemaCommand,
+}
 
-    let _guard = FileCleanupGuard {
-        files: vec![&manifest_file_path],
-        dirs: vec![&out_patch_dir],
-    };
+#[derive(Subcommand, Debug)]
+enum SchemaCommand {
+    /// Print the JSON Schema of the newest release `manifest.json` format.
+    Print,
+    /// Validate a release `manifest.json` against the JSON Schema of its
+    /// format version.
+    Validate {
+        /// Path to the `manifest.json`.
+        manifest: PathBuf,
+    },
+}
 
-    let base_src_files: Vec<_> = rec_get_all_files_in_tree(base_src_root)
-        .context("Getting all files in base dir")?
-        .into_iter()
-        .map(|file| {
-            file.strip_prefix(&args.base)
-                .expect("Prefix should be valid")
-                .to_path_buf()
-        })
-        .collect();
-    let new_src_files: Vec<_> = rec_get_all_files_in_tree(new_src_root)
-        .context("Getting all files in new dir")?
-        .into_iter()
-        .map(|file| {
-            file.strip_prefix(&args.new)
-                .expect("Prefix should be valid")
-                .to_path_buf()
-        })
-        .collect();
-
-    let mut actions = vec![];
-
-    for base_file in &base_src_files {
-        if !new_src_files.contains(base_file) {
-            let path = base_file.to_str().expect(PATH_TO_STR_ERROR).to_string();
-            actions.push(Action::Delete { path });
-        } else {
-            let base_file_full = args.base.clone().join(base_file);
-            let new_file_full = args.new.clone().join(base_file);
-
-            if !files_are_same(&base_file_full, &new_file_full)? {
-                let patch_file = out_patch_dir.clone().join(base_file);
-                let patch_file_parent = patch_file
-                    .parent()
-                    .expect("Patch file should have a parent");
-                std::fs::create_dir_all(patch_file_parent)
-                    .with_context(|| format!("Creating dir: {}", patch_file_parent.display()))?;
-                let _ = File::create_new(&patch_file)
-                    .with_context(|| format!("Creating patch file: {}", patch_file.display()))?;
-
-                let output = Command::new(args.updiff_path.as_os_str())
-                    .arg(&args.base_version)
-                    .arg(base_file_full)
-                    .arg(&args.new_version)
-                    .arg(new_file_full)
-                    .arg(&patch_file)
-                    .output()
-                    .context("Running updiff command")?;
-
-                if !output.status.success() {
-                    eprintln!("Error: {}", String::from_utf8_lossy(&output.stderr));
-                    std::process::exit(1);
-                }
+#[derive(clap::Args, Debug)]
+struct CacheArgs {
+    /// Path to the patch cache directory.
+    #[arg(long)]
+    dir: PathBuf,
+    #[command(subcommand)]
+    command: CacheCommand,
+}
 
-                let file = base_file.to_str().expect(PATH_TO_STR_ERROR).to_string();
+#[derive(Subcommand, Debug)]
+enum CacheCommand {
+    /// Print how many patches the cache holds and how much space they take.
+    Inspect {
+        /// List every cached patch.
+        #[arg(long)]
+        list: bool,
+    },
+    /// Remove patches of other patch format versions and leftovers of
+    /// interrupted runs, and optionally the patches not used recently.
+    Prune {
+        /// Also remove the patches that were not used for this many days.
+        #[arg(long, value_name = "DAYS", conflicts_with = "all")]
+        older_than: Option<u64>,
+        /// Remove every patch.
+        #[arg(long)]
+        all: bool,
+    },
+    /// Check that every cached patch is intact.
+    Verify {
+        /// Remove the broken patches instead of failing.
+        #[arg(long)]
+        remove: bool,
+    },
+}
 
-                actions.push(Action::Patch {
-                    patch_file: file.clone(),
-                    patch_source: file,
-                    base_version: args.base_version.clone(),
-                    new_version: args.new_version.clone(),
-                });
-            }
-        }
-    }
-    for new_file in &new_src_files {
-        if !base_src_files.contains(new_file) {
-            let source_file_path = args.new.clone().join(new_file);
-            let mut source_file = File::open(&source_file_path).expect("Source should file exist");
-            let patch_file_path = out_patch_dir.clone().join(new_file);
-            let patch_file_parent = patch_file_path
-                .parent()
-                .expect("Patch file should have parent");
-            std::fs::create_dir_all(patch_file_parent)
-                .with_context(|| format!("Creating dir: {}", patch_file_parent.display()))?;
-
-            let mut out_file = std::fs::File::create_new(&patch_file_path)
-                .with_context(|| format!("Creating patch file: {}", patch_file_path.display()))?;
-
-            let file_path = new_file.to_str().expect(PATH_TO_STR_ERROR).to_string();
-            std::io::copy(&mut source_file, &mut out_file).with_context(|| {
-                format!(
-                    "Copying file from {} to {}",
-                    source_file_path.display(),
-                    patch_file_path.display()
-                )
-            })?;
-            actions.push(Action::Add {
-                source: file_path.clone(),
-                dest: file_path,
-            });
-        }
+fn parse_format_version(version: &str) -> Result<u32, String> {
+    let version = version.parse().map_err(|err| format!("{err}"))?;
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(format!(
+            "supported versions are {MIN_FORMAT_VERSION} to {FORMAT_VERSION}"
+        ));
     }
+    Ok(version)
+}
 
-    let actions = vec![Action::Transaction { actions }];
-
-    let manifest = ReleaseManifest {
-        label: args.label.clone(),
-        mandatory: args.mandatory,
-        date: chrono::Utc::now().date_naive().to_string(),
-        actions,
+/// Parses a size in bytes, with an optional `K` or `M` suffix for KiB and
+/// MiB.
+fn parse_size(size: &str) -> Result<NonZeroU64, String> {
+    let (number, unit) = match size.strip_suffix(['K', 'k']) {
+        Some(number) => (number, 1 << 10),
+        None => match size.strip_suffix(['M', 'm']) {
+            Some(number) => (number, 1 << 20),
+            None => (size, 1),
+        },
     };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(unit))
+        .and_then(NonZeroU64::new)
+        .ok_or_else(|| format!("expected a size like 65536, 64K or 1M, got `{size}`"))
+}
 
-    manifest_file
-        .write_all(
-            serde_json::to_string(&manifest)
-                .expect("Serialization should not fail")
-                .as_bytes(),
-        )
-        .context("Writing to manifest.json")?;
-
-    let mut tar = tar::Builder::new(tar_file);
-    tar.append_dir_all("patch", &out_patch_dir)?;
-    tar.append_file("manifest.json", &mut manifest_file)?;
-
-    Ok(())
+/// The first 12 characters of a hex hash, or all of it if shorter, e.g. in
+/// a hand-edited cache info file.
+fn short_hash(hash: &str) -> &str {
+    hash.get(..12).unwrap_or(hash)
 }
 
-struct FileCleanupGuard<'a> {
-    files: Vec<&'a Path>,
-    dirs: Vec<&'a Path>,
+fn parse_base(base: &str) -> Result<(String, PathBuf), String> {
+    let (version, path) = base
+        .split_once('=')
+        .ok_or_else(|| format!("expected VERSION=PATH, got `{base}`"))?;
+    Ok((version.to_string(), PathBuf::from(path)))
 }
 
-impl Drop for FileCleanupGuard<'_> {
-    fn drop(&mut self) {
-        for file in &self.files {
-            if let Err(err) = std::fs::remove_file(file) {
-                eprintln!("Error removing file {}: {}", file.display(), err);
-            }
+fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse_args();
+    match cli.command {
+        Some(Command::FanOut(args)) => {
+            let args = *args;
+            print_build_report(&fan_out(FanOutRequest {
+                new_version: args.new_version,
+                new: args.new,
+                bases: args.bases,
+                options: args.options.into(),
+                out_dir: args.out_dir,
+            })?);
+            Ok(())
         }
-        for dir in &self.dirs {
-            if let Err(err) = std::fs::remove_dir_all(dir) {
-                eprintln!("Error removing directory {}: {}", dir.display(), err);
-            }
+        Some(Command::Compose(args)) => {
+            print_build_report(&compose(args.into())?);
+            Ok(())
         }
+        Some(Command::Apply(args)) => apply(args),
+        Some(Command::Check(args)) => check(args),
+        Some(Command::Schema(args)) => schema(args),
+        Some(Command::Cache(args)) => cache(args),
+        None => match cli.args {
+            Some(args) => {
+                print_build_report(&generate(GenerateRequest {
+                    base_version: args.base_version,
+                    base: args.base,
+                    new_version: args.new_version,
+                    new: args.new,
+                    git: args.git,
+                    options: args.options.into(),
+                    out: args.out,
+                })?);
+                Ok(())
+            }
+            None => Cli::command()
+                .error(
+                    ErrorKind::MissingRequiredArgument,
+                    "the versions and directories of the release are required",
+                )
+                .exit(),
+        },
     }
 }
 
-fn rec_get_all_files_in_tree(dir: ReadDir) -> anyhow::Result<Vec<PathBuf>> {
-    let mut file_paths = vec![];
-
-    for entry in dir {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
-
-        if metadata.is_symlink() {
+/// Prints which files were left out of the releases, how the patch cache was
+/// used and how long each phase took.
+fn print_build_report(report: &BuildReport) {
+    for (dir, excluded) in &report.excluded {
+        if excluded.is_empty() {
             continue;
-        } else if metadata.is_file() {
-            file_paths.push(entry.path());
-        } else if metadata.is_dir() {
-            let subdir = std::fs::read_dir(entry.path())
-                .with_context(|| format!("Reading subdirectory: {}", entry.path().display()))?;
-            file_paths.extend(rec_get_all_files_in_tree(subdir)?);
+        }
+        println!("Excluded from {}:", dir.display());
+        for path in excluded {
+            println!("  {}", path.display());
         }
     }
-
-    Ok(file_paths)
+    if let Some((hits, misses)) = report.patch_cache_stats {
+        println!("Patch cache: {hits} reused, {misses} generated");
+    }
+    println!("{}", report.timings);
 }
 
-fn files_are_same(file_path1: &Path, file_path2: &Path) -> anyhow::Result<bool> {
-    let metadata1 = std::fs::metadata(file_path1)
-        .with_context(|| format!("Reading metadata from: {}", file_path1.display()))?;
-    let metadata2 = std::fs::metadata(file_path2)
-        .with_context(|| format!("Reading metadata from: {}", file_path2.display()))?;
+/// Applies the release to a copy of the base directory.
+fn apply(args: ApplyArgs) -> anyhow::Result<()> {
+    let release = ReleaseArchive::open(&args.release)?;
+    let violations = constraints::evaluate(&release.manifest.constraints, &args.device);
+    if !violations.is_empty() {
+        for violation in &violations {
+            eprintln!("{}: {violation}", args.release.display());
+        }
+        anyhow::bail!("Device does not meet the release constraints");
+    }
+    let base = TreeSnapshot::new(&args.base).context("Reading base dir")?;
+    anyhow::ensure!(
+        !args.out_dir.exists(),
+        "Output dir ({}) already exists",
+        args.out_dir.display()
+    );
+    copy_tree(&base, &args.out_dir)?;
+    apply_release(&release, &args.out_dir)
+}
 
-    if metadata1.len() != metadata2.len() {
-        return Ok(false);
+/// Opens the release tar and prints every issue found in it.
+fn check(args: CheckArgs) -> anyhow::Result<()> {
+    let release = ReleaseArchive::open(&args.release)?;
+    let mut issues = match &args.config_dir {
+        Some(dir) => {
+            check_release_with_settings(&release, &ReleaseConfig::load(dir)?.settings.known)
+        }
+        None => check_release(&release),
+    };
+    issues.extend(
+        constraints::evaluate(&release.manifest.constraints, &args.device)
+            .into_iter()
+            .map(Issue::NotApplicable),
+    );
+
+    if issues.is_empty() {
+        println!("{}: OK", args.release.display());
+        return Ok(());
+    }
+    for issue in &issues {
+        println!("{}: {issue}", args.release.display());
     }
+    anyhow::bail!(
+        "Found {} issue(s) in {}",
+        issues.len(),
+        args.release.display()
+    )
+}
 
-    let mut file1 = File::open(file_path1)
-        .with_context(|| format!("Opening file: {}", file_path1.display()))?;
-    let mut file2 = File::open(file_path2)
-        .with_context(|| format!("Opening file: {}", file_path2.display()))?;
-
-    let mut buffer1 = [0; 1024];
-    let mut buffer2 = [0; 1024];
-
-    loop {
-        let bytes_read1 = file1
-            .read(&mut buffer1)
-            .with_context(|| format!("Reading chunk from: {}", file_path1.display()))?;
-        let bytes_read2 = file2
-            .read(&mut buffer2)
-            .with_context(|| format!("Reading chunk from: {}", file_path1.display()))?;
-
-        if bytes_read1 == 0 {
-            debug_assert_eq!(bytes_read2, 0);
-            break;
+fn schema(args: SchemaArgs) -> anyhow::Result<()> {
+    match args.command {
+        SchemaCommand::Print => {
+            println!(
+                "{}",
+                serde_json::to_string_pretty(&release_manifest_schema())
+                    .expect("Serialization should not fail")
+            );
+        }
+        SchemaCommand::Validate { manifest } => {
+            let contents = std::fs::read(&manifest)
+                .with_context(|| format!("Reading manifest: {}", manifest.display()))?;
+            let (format_version, errors) = validate_manifest(&contents)
+                .with_context(|| format!("Validating manifest: {}", manifest.display()))?;
+            if errors.is_empty() {
+                println!("{}: OK", manifest.display());
+                return Ok(());
+            }
+            for error in &errors {
+                println!("{}: {error}", manifest.display());
+            }
+            anyhow::bail!(
+                "{} does not match the release manifest schema v{format_version}",
+                manifest.display()
+            );
         }
+    }
+    Ok(())
+}
 
-        if buffer1[..bytes_read1] != buffer2[..bytes_read2] {
-            return Ok(false);
+fn cache(args: CacheArgs) -> anyhow::Result<()> {
+    match args.command {
+        CacheCommand::Inspect { list } => {
+            let contents = CacheContents::read(&args.dir)?;
+            println!(
+                "{}: {} patch(es), {} bytes (patch format version {PATCH_FORMAT_VERSION})",
+                args.dir.display(),
+                contents.entries.len(),
+                contents.size()
+            );
+            if list {
+                for entry in &contents.entries {
+                    let last_used = chrono::DateTime::<chrono::Utc>::from(entry.last_used);
+                    let Some(key) = entry.key() else {
+                        println!("  {}: unreadable", entry.id);
+                        continue;
+                    };
+                    println!(
+                        "  {}: {} {} -> {} {}, {} bytes, last used {}",
+                        entry.id,
+                        key.base_version,
+                        short_hash(&key.base_sha256),
+                        key.new_version,
+                        short_hash(&key.new_sha256),
+                        entry.size,
+                        last_used.format("%Y-%m-%d %H:%M")
+                    );
+                }
+            }
+            if !contents.other_versions.is_empty() || !contents.leftovers.is_empty() {
+                println!(
+                    "{} dir(s) of other patch format versions and {} leftover file(s), run \
+                     `prune` to remove them",
+                    contents.other_versions.len(),
+                    contents.leftovers.len()
+                );
+            }
+            Ok(())
+        }
+        CacheCommand::Prune { older_than, all } => {
+            let filter = match (older_than, all) {
+                (_, true) => PruneFilter::All,
+                (Some(days), false) => PruneFilter::UnusedFor(Duration::from_secs(days * 86400)),
+                (None, false) => PruneFilter::Stale,
+            };
+            let removed = prune(&args.dir, filter)?;
+            println!("{}: removed {removed} patch(es)", args.dir.display());
+            Ok(())
         }
+        CacheCommand::Verify { remove } => {
+            let contents = CacheContents::read(&args.dir)?;
+            let mut broken = 0;
+            for entry in &contents.entries {
+                if let Err(err) = entry.verify() {
+                    println!("{}: {err:#}", entry.id);
+                    if remove {
+                        entry.remove()?;
+                    } else {
+                        broken += 1;
+                    }
+                }
+            }
+            anyhow::ensure!(
+                broken == 0,
+                "Found {broken} broken patch(es) in {}",
+                args.dir.display()
+            );
+            println!("{}: OK", args.dir.display());
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::{CacheCommand, Cli, Command, parse_size, short_hash},
+        clap::CommandFactory,
+        std::path::PathBuf,
+    };
+
+    #[test]
+    fn cli_parses_generate_args_and_subcommands() {
+        let parse = |args: &[&str]| {
+            let matches = Cli::command().try_get_matches_from(args).unwrap();
+            Cli::from_matches(&matches).unwrap()
+        };
+
+        let cli = parse(&["release-gen", "v1", "base", "v2", "new", "--mandatory"]);
+        let args = cli.args.unwrap();
+        assert_eq!(args.base_version, "v1");
+        assert_eq!(args.new, PathBuf::from("new"));
+        assert!(args.options.mandatory);
+
+        // `--open-app` needs a route.
+        let args = [
+            "release-gen",
+            "v1",
+            "base",
+            "v2",
+            "new",
+            "--open-app",
+            "0x00",
+        ];
+        assert!(Cli::command().try_get_matches_from(args).is_err());
+
+        let cli = parse(&["release-gen", "check", "release.tar"]);
+        assert!(matches!(cli.command, Some(Command::Check(_))));
+        assert!(cli.args.is_none());
+
+        let cli = parse(&[
+            "release-gen",
+            "cache",
+            "--dir",
+            "cache",
+            "prune",
+            "--older-than",
+            "30",
+        ]);
+        let Some(Command::Cache(args)) = cli.command else {
+            panic!("Expected the cache subcommand");
+        };
+        assert!(matches!(
+            args.command,
+            CacheCommand::Prune {
+                older_than: Some(30),
+                all: false
+            }
+        ));
     }
 
-    Ok(true)
+    #[test]
+    fn chunk_sizes_parse() {
+        assert_eq!(parse_size("65536").unwrap().get(), 65536);
+        assert_eq!(parse_size("64K").unwrap().get(), 64 << 10);
+        assert_eq!(parse_size("1M").unwrap().get(), 1 << 20);
+        assert!(parse_size("0").is_err());
+        assert!(parse_size("1G").is_err());
+    }
+
+    #[test]
+    fn short_hashes_of_truncated_info_files() {
+        assert_eq!(short_hash("0123456789abcdef"), "0123456789ab");
+        assert_eq!(short_hash("0123"), "0123");
+    }
 }
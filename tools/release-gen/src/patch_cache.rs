@@ -0,0 +1,402 @@
+use {
+    anyhow::Context,
+    serde::{Deserialize, Serialize},
+    sha2::{Digest, Sha256},
+    std::{
+        collections::BTreeMap,
+        fs::File,
+        io::Write,
+        path::{Path, PathBuf},
+        sync::atomic::{AtomicUsize, Ordering},
+        time::{Duration, SystemTime},
+    },
+};
+
+/// Version of the patches stored in the cache. Bump it whenever the patches
+/// `release-gen` expects from `updiff` change, so that older patches are not
+/// reused anymore. Entries of other versions are left alone until pruned.
+pub const PATCH_FORMAT_VERSION: u32 = 1;
+
+/// What a cached patch is the patch of. `updiff` writes the versions into the
+/// patch header, so they are part of the key along with the file hashes.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PatchKey {
+    /// Hex encoded SHA-256 of the base file.
+    pub base_sha256: String,
+    /// Hex encoded SHA-256 of the new file.
+    pub new_sha256: String,
+    pub base_version: String,
+    pub new_version: String,
+}
+
+impl PatchKey {
+    pub fn new(
+        base_sha256: &[u8; 32],
+        new_sha256: &[u8; 32],
+        base_version: &str,
+        new_version: &str,
+    ) -> Self {
+        Self {
+            base_sha256: hex::encode(base_sha256),
+            new_sha256: hex::encode(new_sha256),
+            base_version: base_version.to_string(),
+            new_version: new_version.to_string(),
+        }
+    }
+
+    /// Name of the cache entry, a hash over the patch format version and the
+    /// key.
+    fn id(&self) -> String {
+        let mut hasher = Sha256::new();
+        for part in [
+            &PATCH_FORMAT_VERSION.to_string(),
+            &self.base_sha256,
+            &self.new_sha256,
+            &self.base_version,
+            &self.new_version,
+        ] {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// The `<id>.json` stored next to every cached `<id>.patch`.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct EntryInfo {
+    #[serde(flatten)]
+    key: PatchKey,
+    /// Hex encoded SHA-256 of the patch.
+    patch_sha256: String,
+}
+
+/// Patches generated by earlier runs, stored by [`PatchKey`] under
+/// `<dir>/v<PATCH_FORMAT_VERSION>/`.
+///
+/// Entries are written to temporary files first and then renamed, so runs
+/// writing the same entry at the same time don't see each other's partial
+/// files. An entry whose patch doesn't match its recorded hash is treated as
+/// missing and overwritten.
+#[derive(Debug)]
+pub struct PatchCache {
+    dir: PathBuf,
+    hits: AtomicUsize,
+    misses: AtomicUsize,
+}
+
+impl PatchCache {
+    /// Opens the cache in `dir`, creating the directory if missing.
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        let dir = version_dir(dir);
+        std::fs::create_dir_all(&dir)
+            .with_context(|| format!("Creating patch cache dir: {}", dir.display()))?;
+        Ok(Self {
+            dir,
+            hits: AtomicUsize::new(0),
+            misses: AtomicUsize::new(0),
+        })
+    }
+
+    /// Copies the cached patch for `key` to `dest`. Returns whether there was
+    /// one.
+    pub fn get(&self, key: &PatchKey, dest: &Path) -> anyhow::Result<bool> {
+        let (patch_path, info_path) = self.entry_paths(&key.id());
+        let patch = read_entry(&patch_path, &info_path)
+            .ok()
+            .filter(|(info, _)| info.key == *key);
+        let Some((_, patch)) = patch else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Ok(false);
+        };
+
+        std::fs::write(dest, patch)
+            .with_context(|| format!("Writing patch file: {}", dest.display()))?;
+        // The modification time of the info file is when the entry was last
+        // used, see `prune`.
+        if let Ok(file) = File::options().write(true).open(&info_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    /// Stores the patch at `patch` for `key`.
+    pub fn insert(&self, key: &PatchKey, patch: &Path) -> anyhow::Result<()> {
+        let patch = std::fs::read(patch)
+            .with_context(|| format!("Reading patch file: {}", patch.display()))?;
+        let info = EntryInfo {
+            key: key.clone(),
+            patch_sha256: hex::encode(Sha256::digest(&patch)),
+        };
+        let (patch_path, info_path) = self.entry_paths(&key.id());
+        let shard = patch_path.parent().expect("Entries are in a shard dir");
+        std::fs::create_dir_all(shard)
+            .with_context(|| format!("Creating patch cache dir: {}", shard.display()))?;
+
+        // The patch goes first, an info file is only ever next to a complete
+        // patch.
+        write_atomic(&patch_path, &patch)?;
+        write_atomic(
+            &info_path,
+            serde_json::to_string(&info)
+                .expect("Serialization should not fail")
+                .as_bytes(),
+        )
+    }
+
+    /// Number of patches taken from the cache and of patches that had to be
+    /// generated so far.
+    pub fn stats(&self) -> (usize, usize) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+        )
+    }
+
+    fn entry_paths(&self, id: &str) -> (PathBuf, PathBuf) {
+        let shard = self.dir.join(&id[..2]);
+        (
+            shard.join(format!("{id}.patch")),
+            shard.join(format!("{id}.json")),
+        )
+    }
+}
+
+fn version_dir(dir: &Path) -> PathBuf {
+    dir.join(format!("v{PATCH_FORMAT_VERSION}"))
+}
+
+fn read_entry(patch_path: &Path, info_path: &Path) -> anyhow::Result<(EntryInfo, Vec<u8>)> {
+    let info =
+        std::fs::read(info_path).with_context(|| format!("Reading {}", info_path.display()))?;
+    let info: EntryInfo = serde_json::from_slice(&info)
+        .with_context(|| format!("Parsing {}", info_path.display()))?;
+    let patch =
+        std::fs::read(patch_path).with_context(|| format!("Reading {}", patch_path.display()))?;
+    anyhow::ensure!(
+        hex::encode(Sha256::digest(&patch)) == info.patch_sha256,
+        "{} does not match its hash",
+        patch_path.display()
+    );
+    Ok((info, patch))
+}
+
+fn write_atomic(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let dir = path.parent().expect("Entries are in a shard dir");
+    let mut file = tempfile::NamedTempFile::new_in(dir)
+        .with_context(|| format!("Creating temporary file in {}", dir.display()))?;
+    file.write_all(contents)
+        .with_context(|| format!("Writing {}", file.path().display()))?;
+    file.persist(path)
+        .with_context(|| format!("Writing {}", path.display()))?;
+    Ok(())
+}
+
+/// A patch in the cache.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub id: String,
+    patch_path: PathBuf,
+    info_path: PathBuf,
+    /// Size of the patch in bytes.
+    pub size: u64,
+    pub last_used: SystemTime,
+}
+
+impl CacheEntry {
+    /// Checks that the patch matches its recorded hash and is stored under
+    /// the right id.
+    pub fn verify(&self) -> anyhow::Result<()> {
+        let (info, _) = read_entry(&self.patch_path, &self.info_path)?;
+        anyhow::ensure!(
+            info.key.id() == self.id,
+            "{} is stored under the wrong id",
+            self.info_path.display()
+        );
+        Ok(())
+    }
+
+    /// What the patch is the patch of, if its info file is readable.
+    pub fn key(&self) -> Option<PatchKey> {
+        let info = std::fs::read(&self.info_path).ok()?;
+        serde_json::from_slice::<EntryInfo>(&info)
+            .ok()
+            .map(|info| info.key)
+    }
+
+    /// Removes the patch and its info file.
+    pub fn remove(&self) -> anyhow::Result<()> {
+        // The info file goes first, so an interrupted removal leaves an
+        // orphaned patch, which `prune` removes.
+        for path in [&self.info_path, &self.patch_path] {
+            match std::fs::remove_file(path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err).with_context(|| format!("Removing {}", path.display()));
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Contents of a patch cache directory.
+#[derive(Debug, Default)]
+pub struct CacheContents {
+    /// Patches of the current patch format version, sorted by id.
+    pub entries: Vec<CacheEntry>,
+    /// Directories of other patch format versions.
+    pub other_versions: Vec<PathBuf>,
+    /// Files that are not part of any entry, e.g. temporary files of
+    /// interrupted runs or patches without an info file.
+    pub leftovers: Vec<PathBuf>,
+}
+
+impl CacheContents {
+    /// Lists what is in the patch cache in `dir`. A missing directory is an
+    /// empty cache.
+    pub fn read(dir: &Path) -> anyhow::Result<Self> {
+        let mut contents = Self::default();
+        if !dir.exists() {
+            return Ok(contents);
+        }
+        let current = version_dir(dir);
+        for entry in read_dir(dir)? {
+            let path = entry.path();
+            let name = entry.file_name();
+            let is_version_dir = name
+                .to_str()
+                .and_then(|name| name.strip_prefix('v'))
+                .is_some_and(|version| version.parse::<u32>().is_ok());
+            if path == current {
+                for shard in read_dir(&path)? {
+                    // Stray files next to the shards, e.g. a `.DS_Store`.
+                    if shard.file_type()?.is_dir() {
+                        contents.read_shard(&shard.path())?;
+                    }
+                }
+            } else if is_version_dir && entry.file_type()?.is_dir() {
+                contents.other_versions.push(path);
+            }
+        }
+        contents.entries.sort_by(|a, b| a.id.cmp(&b.id));
+        contents.other_versions.sort();
+        contents.leftovers.sort();
+        Ok(contents)
+    }
+
+    fn read_shard(&mut self, shard: &Path) -> anyhow::Result<()> {
+        let mut files: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+        for entry in read_dir(shard)? {
+            let path = entry.path();
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            files.entry(stem).or_default().push(path);
+        }
+
+        for (id, paths) in files {
+            let patch_path = shard.join(format!("{id}.patch"));
+            let info_path = shard.join(format!("{id}.json"));
+            if !paths.contains(&info_path) {
+                self.leftovers.extend(paths);
+                continue;
+            }
+            let size = std::fs::metadata(&patch_path).map_or(0, |metadata| metadata.len());
+            let last_used = std::fs::metadata(&info_path)
+                .and_then(|metadata| metadata.modified())
+                .with_context(|| format!("Reading {}", info_path.display()))?;
+            self.leftovers.extend(
+                paths
+                    .into_iter()
+                    .filter(|path| *path != patch_path && *path != info_path),
+            );
+            self.entries.push(CacheEntry {
+                id,
+                patch_path,
+                info_path,
+                size,
+                last_used,
+            });
+        }
+        Ok(())
+    }
+
+    /// Total size of the patches in bytes.
+    pub fn size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
+
+fn read_dir(dir: &Path) -> anyhow::Result<Vec<std::fs::DirEntry>> {
+    std::fs::read_dir(dir)
+        .with_context(|| format!("Reading dir: {}", dir.display()))?
+        .map(|entry| entry.with_context(|| format!("Reading dir: {}", dir.display())))
+        .collect()
+}
+
+/// How long a leftover has to be untouched before [`prune`] removes it. A
+/// younger one may be a temporary file or a patch without its info file yet
+/// of a run writing to the cache right now.
+pub const LEFTOVER_MIN_AGE: Duration = Duration::from_secs(60 * 60);
+
+/// Removes the directories of other patch format versions, the leftovers
+/// older than [`LEFTOVER_MIN_AGE`], and the patches selected by `filter`.
+/// Returns the number of removed patches.
+pub fn prune(dir: &Path, filter: PruneFilter) -> anyhow::Result<usize> {
+    let contents = CacheContents::read(dir)?;
+    for other in &contents.other_versions {
+        std::fs::remove_dir_all(other).with_context(|| format!("Removing {}", other.display()))?;
+    }
+    let now = SystemTime::now();
+    for leftover in &contents.leftovers {
+        let modified = match std::fs::metadata(leftover).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified,
+            // Renamed into place by a concurrent run in the meantime.
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err).with_context(|| format!("Reading {}", leftover.display())),
+        };
+        if now
+            .duration_since(modified)
+            .is_ok_and(|age| age >= LEFTOVER_MIN_AGE)
+        {
+            match std::fs::remove_file(leftover) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    return Err(err).with_context(|| format!("Removing {}", leftover.display()));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    let mut removed = 0;
+    for entry in &contents.entries {
+        let remove = match filter {
+            PruneFilter::All => true,
+            PruneFilter::UnusedFor(age) => now
+                .duration_since(entry.last_used)
+                .is_ok_and(|unused| unused >= age),
+            PruneFilter::Stale => false,
+        };
+        if remove {
+            entry.remove()?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+/// Which patches of the current patch format version [`prune`] removes.
+#[derive(Debug, Clone, Copy)]
+pub enum PruneFilter {
+    /// None of them.
+    Stale,
+    /// The ones not used for the given time.
+    UnusedFor(Duration),
+    /// All of them.
+    All,
+}
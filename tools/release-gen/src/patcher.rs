@@ -0,0 +1,194 @@
+use {
+    crate::{
+        create_parent,
+        error::{GenerateError, path_to_string},
+        patch_cache::{PatchCache, PatchKey},
+        release_manifest::Action,
+        tree::{TreeFile, TreeSnapshot},
+    },
+    anyhow::Context,
+    rayon::prelude::*,
+    sha2::{Digest, Sha256},
+    std::{
+        fs::File,
+        io::Write,
+        num::NonZeroU64,
+        path::{Path, PathBuf},
+        process,
+    },
+};
+
+/// Creates the patches of the changed files of a release in its patch dir.
+pub struct Patcher<'a> {
+    pub updiff_path: &'a Path,
+    pub cache: Option<&'a PatchCache>,
+    pub base_version: &'a str,
+    pub new_version: &'a str,
+    pub out_patch_dir: &'a Path,
+    /// Where the windows of chunked files are written for `updiff`.
+    pub scratch_dir: &'a Path,
+    /// Files larger than this, before or after the update, are patched in
+    /// windows of this size.
+    pub chunk_size: Option<NonZeroU64>,
+}
+
+impl Patcher<'_> {
+    /// Creates the patch actions turning `base_file` into `new_file`, a
+    /// single `patch` or a `patch-chunk` per changed window.
+    pub fn patch_file(
+        &self,
+        base: &TreeSnapshot,
+        base_file: &TreeFile,
+        new: &TreeSnapshot,
+        new_file: &TreeFile,
+    ) -> anyhow::Result<Vec<Action>> {
+        let file = path_to_string(&new_file.path)?;
+        let base_path = base.full_path(&base_file.path);
+        let new_path = new.full_path(&new_file.path);
+        match self.chunk_size {
+            Some(chunk_size) if base_file.size.max(new_file.size) > chunk_size.get() => {
+                self.patch_chunks(&file, &base_path, &new_path, chunk_size.get())
+            }
+            _ => {
+                let key = PatchKey::new(
+                    &base_file.sha256,
+                    &new_file.sha256,
+                    self.base_version,
+                    self.new_version,
+                );
+                self.run_updiff(&key, &base_path, &new_path, &new_file.path)?;
+                Ok(vec![Action::Patch {
+                    patch_file: file.clone(),
+                    patch_source: file,
+                    base_version: self.base_version.to_string(),
+                    new_version: self.new_version.to_string(),
+                }])
+            }
+        }
+    }
+
+    /// Patches every window of the new file that differs from the window at
+    /// the same offset of the base file. The last window is always patched,
+    /// as applying it truncates the file to its new size.
+    fn patch_chunks(
+        &self,
+        file: &str,
+        base_path: &Path,
+        new_path: &Path,
+        chunk_size: u64,
+    ) -> anyhow::Result<Vec<Action>> {
+        let base =
+            std::fs::read(base_path).with_context(|| format!("Reading {}", base_path.display()))?;
+        let new =
+            std::fs::read(new_path).with_context(|| format!("Reading {}", new_path.display()))?;
+        let chunk_len = usize::try_from(chunk_size).context("Chunk size is too large")?;
+        let windows = new.len().div_ceil(chunk_len).max(1);
+
+        (0..windows)
+            .into_par_iter()
+            .filter_map(|index| {
+                let offset = index * chunk_len;
+                let window = |data: &[u8]| -> Vec<u8> {
+                    data[offset.min(data.len())..(offset + chunk_len).min(data.len())].to_vec()
+                };
+                let (base_window, new_window) = (window(&base), window(&new));
+                if base_window == new_window && index + 1 < windows {
+                    return None;
+                }
+                Some(self.patch_chunk(file, index, offset, &base_window, &new_window, new.len()))
+            })
+            .collect()
+    }
+
+    fn patch_chunk(
+        &self,
+        file: &str,
+        index: usize,
+        offset: usize,
+        base_window: &[u8],
+        new_window: &[u8],
+        file_size: usize,
+    ) -> anyhow::Result<Action> {
+        let base_sha256: [u8; 32] = Sha256::digest(base_window).into();
+        let new_sha256: [u8; 32] = Sha256::digest(new_window).into();
+        let key = PatchKey::new(
+            &base_sha256,
+            &new_sha256,
+            self.base_version,
+            self.new_version,
+        );
+        let name = format!("{file}.chunks/{index:04}");
+
+        let base_window_file = self.scratch_file(base_window)?;
+        let new_window_file = self.scratch_file(new_window)?;
+        self.run_updiff(
+            &key,
+            base_window_file.path(),
+            new_window_file.path(),
+            Path::new(&name),
+        )?;
+
+        Ok(Action::PatchChunk {
+            patch_file: name,
+            patch_source: file.to_string(),
+            base_version: self.base_version.to_string(),
+            new_version: self.new_version.to_string(),
+            offset: offset as u64,
+            base_length: base_window.len() as u64,
+            base_sha256: key.base_sha256,
+            new_length: new_window.len() as u64,
+            new_sha256: key.new_sha256,
+            file_size: file_size as u64,
+        })
+    }
+
+    fn scratch_file(&self, contents: &[u8]) -> anyhow::Result<tempfile::NamedTempFile> {
+        let mut file = tempfile::NamedTempFile::new_in(self.scratch_dir).with_context(|| {
+            format!("Creating temporary file in {}", self.scratch_dir.display())
+        })?;
+        file.write_all(contents)
+            .with_context(|| format!("Writing {}", file.path().display()))?;
+        Ok(file)
+    }
+
+    /// Runs `updiff` to create the patch `name` (relative to the patch dir),
+    /// unless the cache already has the patch for `key`.
+    fn run_updiff(
+        &self,
+        key: &PatchKey,
+        base_file: &Path,
+        new_file: &Path,
+        name: &Path,
+    ) -> anyhow::Result<()> {
+        let patch_file: PathBuf = self.out_patch_dir.join(name);
+        create_parent(&patch_file)?;
+        let _ = File::create_new(&patch_file)
+            .with_context(|| format!("Creating patch file: {}", patch_file.display()))?;
+        if let Some(cache) = self.cache
+            && cache.get(key, &patch_file)?
+        {
+            return Ok(());
+        }
+
+        let output = process::Command::new(self.updiff_path.as_os_str())
+            .arg(&key.base_version)
+            .arg(base_file)
+            .arg(&key.new_version)
+            .arg(new_file)
+            .arg(&patch_file)
+            .output()
+            .context("Running updiff command")?;
+
+        if !output.status.success() {
+            return Err(GenerateError::UpdiffFailed {
+                path: name.to_path_buf(),
+                stderr: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+            }
+            .into());
+        }
+        if let Some(cache) = self.cache {
+            cache.insert(key, &patch_file)?;
+        }
+        Ok(())
+    }
+}
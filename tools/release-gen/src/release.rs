@@ -0,0 +1,595 @@
+use {
+    crate::{
+        MetadataPolicy,
+        ReleaseOptions,
+        apps,
+        create_parent,
+        error::{GenerateError, path_to_string},
+        patch_cache::PatchCache,
+        patcher::Patcher,
+        release_manifest::{Action, Compression, FORMAT_VERSION, ReleaseManifest, sort_actions},
+        report::ReleaseReport,
+        timing::Timings,
+        tree::{TreeDiff, TreeFile, TreeSnapshot},
+    },
+    anyhow::Context,
+    rayon::prelude::*,
+    std::{
+        collections::BTreeMap,
+        fs::{DirEntry, File, Permissions},
+        io::{self, Write},
+        num::NonZeroUsize,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Environment variable with the time releases are dated at, in seconds
+/// since the Unix epoch. See <https://reproducible-builds.org/specs/source-date-epoch/>.
+pub const SOURCE_DATE_EPOCH: &str = "SOURCE_DATE_EPOCH";
+
+/// Builds the release that updates `base` to `new`.
+///
+/// [`ReleaseBuilder::plan`] diffs the trees, generates the payloads into a
+/// staging dir and computes the manifest. The plan can then be inspected and
+/// written as a release tar with [`ReleasePlan::write`].
+pub struct ReleaseBuilder<'a> {
+    base_version: String,
+    base: &'a TreeSnapshot,
+    new_version: String,
+    new: &'a TreeSnapshot,
+    options: ReleaseOptions,
+    device_actions: Vec<Action>,
+    staging_dir: Option<PathBuf>,
+    source_date_epoch: Option<String>,
+}
+
+impl<'a> ReleaseBuilder<'a> {
+    pub fn new(
+        base_version: impl Into<String>,
+        base: &'a TreeSnapshot,
+        new_version: impl Into<String>,
+        new: &'a TreeSnapshot,
+    ) -> Self {
+        Self {
+            base_version: base_version.into(),
+            base,
+            new_version: new_version.into(),
+            new,
+            options: ReleaseOptions::default(),
+            device_actions: vec![],
+            staging_dir: None,
+            source_date_epoch: std::env::var(SOURCE_DATE_EPOCH).ok(),
+        }
+    }
+
+    /// Options of the release. The report and rollback options are ignored,
+    /// they are up to the caller.
+    pub fn options(mut self, options: ReleaseOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Actions run on the device after the file actions, e.g. settings
+    /// migrations. An `update-bt` action is added right after the file
+    /// actions when the Bluetooth firmware changes, unless there already is
+    /// one. The `open-app` action of the options comes last, after the
+    /// transaction.
+    pub fn device_actions(mut self, actions: Vec<Action>) -> Self {
+        self.device_actions = actions;
+        self
+    }
+
+    /// Directory the private staging dir is created in. Defaults to the
+    /// system temporary directory.
+    pub fn staging_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.staging_dir = Some(dir.into());
+        self
+    }
+
+    /// Value of `SOURCE_DATE_EPOCH` the release is dated at when the options
+    /// have no date. Defaults to the environment variable.
+    pub fn source_date_epoch(mut self, epoch: Option<String>) -> Self {
+        self.source_date_epoch = epoch;
+        self
+    }
+
+    /// Diffs the trees, generates the patches and computes the manifest.
+    pub fn plan(self) -> anyhow::Result<ReleasePlan<'a>> {
+        let Self {
+            base_version,
+            base,
+            new_version,
+            new,
+            options,
+            device_actions,
+            staging_dir,
+            source_date_epoch,
+        } = self;
+        let bt_firmware = options.bt_firmware_matcher()?;
+        let open_app = options.open_app_action();
+        let time = release_time(options.date.as_deref(), source_date_epoch.as_deref())?;
+        if let Some(Action::OpenApp { app_id, .. }) = &open_app {
+            apps::ensure_app_exists(new, app_id)?;
+        }
+
+        // The payloads and the manifest are staged in a private dir, which is
+        // removed with the plan.
+        let mut staging = tempfile::Builder::new();
+        staging.prefix(".release-gen-");
+        let staging = match &staging_dir {
+            Some(dir) => staging.tempdir_in(dir),
+            None => staging.tempdir(),
+        }
+        .context("Creating staging dir")?;
+        let patch_dir = staging.path().join("patch");
+        std::fs::create_dir(&patch_dir)
+            .with_context(|| format!("Creating patch dir: {}", patch_dir.display()))?;
+
+        let mut timings = Timings::default();
+        let diff = timings.time("diff", || TreeDiff::new(base, new));
+        let bt_firmware_changed = diff
+            .changed
+            .iter()
+            .map(|(_, new_file)| *new_file)
+            .chain(diff.added.iter().copied())
+            .any(|file| bt_firmware.is_match(&file.path));
+        let mut actions = vec![];
+
+        for base_file in &diff.deleted {
+            let path = path_to_string(&base_file.path)?;
+            actions.push(Action::Delete { path });
+        }
+
+        let cache = options
+            .patch_cache
+            .as_deref()
+            .map(PatchCache::open)
+            .transpose()?;
+        let patcher = Patcher {
+            updiff_path: &options.updiff_path,
+            cache: cache.as_ref(),
+            base_version: &base_version,
+            new_version: &new_version,
+            out_patch_dir: &patch_dir,
+            scratch_dir: staging.path(),
+            chunk_size: options.chunk_size,
+        };
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(options.jobs.map_or(0, NonZeroUsize::get))
+            .build()
+            .context("Starting the patch workers")?;
+        let patches = timings.time("patches", || {
+            pool.install(|| {
+                diff.changed
+                    .par_iter()
+                    .map(|(base_file, new_file)| patcher.patch_file(base, base_file, new, new_file))
+                    .collect::<anyhow::Result<Vec<_>>>()
+            })
+        })?;
+        actions.extend(patches.into_iter().flatten());
+
+        timings.time("adds", || {
+            for new_file in &diff.added {
+                let file_path = path_to_string(&new_file.path)?;
+                let source_file_path = new.full_path(&new_file.path);
+                let mut source_file = File::open(&source_file_path).map_err(|source| {
+                    GenerateError::SourceUnreadable {
+                        path: new_file.path.clone(),
+                        source,
+                    }
+                })?;
+                let patch_file_path = patch_dir.join(&new_file.path);
+                create_parent(&patch_file_path)?;
+
+                let mut out_file = File::create_new(&patch_file_path).with_context(|| {
+                    format!("Creating patch file: {}", patch_file_path.display())
+                })?;
+
+                io::copy(&mut source_file, &mut out_file).with_context(|| {
+                    format!(
+                        "Copying file from {} to {}",
+                        source_file_path.display(),
+                        patch_file_path.display()
+                    )
+                })?;
+                actions.push(Action::Add {
+                    source: file_path.clone(),
+                    dest: file_path,
+                });
+            }
+            anyhow::Ok(())
+        })?;
+
+        let compression = match options.compression {
+            Some(codec) => timings.time("compress", || {
+                pool.install(|| compress_payloads(codec, &patch_dir, &actions))
+            })?,
+            None => BTreeMap::new(),
+        };
+
+        let metadata = metadata_actions(base, new)?;
+        match options.metadata_policy.unwrap_or_default() {
+            MetadataPolicy::Fail if !metadata.changed.is_empty() => {
+                let paths = metadata
+                    .changed
+                    .iter()
+                    .map(|action| match action {
+                        Action::Delete { path } => format!("  {path}: link removed"),
+                        Action::Symlink { path, target } => format!("  {path}: link to {target}"),
+                        Action::SetMode { path, mode } => format!("  {path}: mode {mode}"),
+                        _ => unreachable!("Not a metadata action: {action:?}"),
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                anyhow::bail!(
+                    "Links or executable bits changed, use `--metadata-policy actions` to put \
+                     them in the release:\n{paths}"
+                );
+            }
+            // Added files get the default mode of the device, as they did
+            // before `set-mode` existed.
+            MetadataPolicy::Fail => {}
+            MetadataPolicy::Actions => {
+                actions.extend(metadata.changed);
+                actions.extend(metadata.added_executables);
+            }
+        }
+
+        sort_actions(&mut actions);
+        // The controller is updated once the new firmware file is in place.
+        if bt_firmware_changed && !device_actions.contains(&Action::UpdateBt) {
+            actions.push(Action::UpdateBt);
+        }
+        actions.extend(device_actions);
+        let actions = std::iter::once(Action::Transaction { actions })
+            .chain(open_app)
+            .collect();
+
+        let manifest = ReleaseManifest {
+            format_version: FORMAT_VERSION,
+            label: options.manifest_label(),
+            notes: options.notes.clone(),
+            mandatory: options.mandatory,
+            rollback: options.is_rollback,
+            commits: options.commits.clone(),
+            constraints: options.constraints.clone(),
+            compression,
+            date: time.date_naive().to_string(),
+            actions,
+        };
+
+        let manifest_json = manifest.to_format_version(options.format_version)?;
+        std::fs::write(
+            staging.path().join("manifest.json"),
+            serde_json::to_string(&manifest_json).expect("Serialization should not fail"),
+        )
+        .context("Writing to manifest.json")?;
+
+        Ok(ReleasePlan {
+            base_version,
+            base,
+            new_version,
+            new,
+            manifest,
+            // Tar can't hold times before the epoch.
+            mtime: u64::try_from(time.timestamp()).unwrap_or(0),
+            build_report: BuildReport {
+                timings,
+                patch_cache_stats: cache.as_ref().map(PatchCache::stats),
+                excluded: [base, new]
+                    .into_iter()
+                    .map(|tree| (tree.root.clone(), tree.excluded.clone()))
+                    .collect(),
+            },
+            staging,
+        })
+    }
+}
+
+/// A release ready to be written: its manifest and the payloads staged for
+/// the tar.
+pub struct ReleasePlan<'a> {
+    base_version: String,
+    base: &'a TreeSnapshot,
+    new_version: String,
+    new: &'a TreeSnapshot,
+    manifest: ReleaseManifest,
+    mtime: u64,
+    build_report: BuildReport,
+    staging: tempfile::TempDir,
+}
+
+/// How generating a release went, for the caller to show.
+#[derive(Debug, Default)]
+pub struct BuildReport {
+    /// How long each phase took.
+    pub timings: Timings,
+    /// How many patches were reused from and added to the patch cache, if
+    /// one is used.
+    pub patch_cache_stats: Option<(usize, usize)>,
+    /// Files and directories left out of each tree, by the root of the tree.
+    pub excluded: BTreeMap<PathBuf, Vec<PathBuf>>,
+}
+
+impl BuildReport {
+    /// Adds the timings and patch cache stats of `other` to these, and the
+    /// trees it left files out of.
+    pub fn add(&mut self, other: BuildReport) {
+        self.timings.add(&other.timings);
+        if let Some((hits, misses)) = other.patch_cache_stats {
+            let (total_hits, total_misses) = self.patch_cache_stats.get_or_insert_default();
+            *total_hits += hits;
+            *total_misses += misses;
+        }
+        self.excluded.extend(other.excluded);
+    }
+}
+
+impl ReleasePlan<'_> {
+    /// The manifest in the newest format version. The tar gets the format
+    /// version of the options.
+    pub fn manifest(&self) -> &ReleaseManifest {
+        &self.manifest
+    }
+
+    pub fn actions(&self) -> &[Action] {
+        &self.manifest.actions
+    }
+
+    /// Directory the payloads are staged in, laid out like the `patch/`
+    /// directory of the tar.
+    pub fn patch_dir(&self) -> PathBuf {
+        self.staging.path().join("patch")
+    }
+
+    /// How planning and writing the release went so far.
+    pub fn build_report(&self) -> &BuildReport {
+        &self.build_report
+    }
+
+    /// Consumes the plan, removing the staging dir, and returns its report.
+    pub fn into_build_report(self) -> BuildReport {
+        self.build_report
+    }
+
+    /// Writes the release tar to `out`, which must not exist. The tar is
+    /// written to a temporary file next to `out`, synced and then renamed,
+    /// so `out` is never a partial tar. The time it takes is added to the
+    /// report.
+    pub fn write(&mut self, out: &Path) -> anyhow::Result<()> {
+        let mut timings = std::mem::take(&mut self.build_report.timings);
+        let written = timings.time("archive", || self.write_tar(out));
+        self.build_report.timings = timings;
+        written
+    }
+
+    fn write_tar(&self, out: &Path) -> anyhow::Result<()> {
+        let out_dir = out_dir(out);
+        std::fs::create_dir_all(out_dir)
+            .with_context(|| format!("Creating output dir: {}", out_dir.display()))?;
+        let tar_file = tempfile::Builder::new()
+            .prefix(".release-gen-")
+            .suffix(".tar")
+            .permissions(Permissions::from_mode(0o644))
+            .tempfile_in(out_dir)
+            .with_context(|| format!("Creating temporary tar file in {}", out_dir.display()))?;
+        let mut tar = tar::Builder::new(tar_file);
+        append_dir_all_sorted(&mut tar, Path::new("patch"), &self.patch_dir(), self.mtime)
+            .context("Adding patch dir to the release tar")?;
+        append_file(
+            &mut tar,
+            Path::new("manifest.json"),
+            &self.staging.path().join("manifest.json"),
+            self.mtime,
+        )
+        .context("Adding manifest.json to the release tar")?;
+        let tar_file = tar.into_inner().context("Finishing the release tar")?;
+        tar_file
+            .as_file()
+            .sync_all()
+            .context("Syncing the release tar")?;
+        tar_file
+            .persist_noclobber(out)
+            .map_err(|err| match err.error.kind() {
+                io::ErrorKind::AlreadyExists => GenerateError::TarExists(out.to_path_buf()).into(),
+                _ => anyhow::Error::new(err.error)
+                    .context(format!("Creating tar file: {}", out.display())),
+            })?;
+        File::open(out_dir)
+            .and_then(|dir| dir.sync_all())
+            .with_context(|| format!("Syncing output dir: {}", out_dir.display()))
+    }
+
+    /// Report of the release, written to a tar of `download_size` bytes.
+    pub fn report(&self, download_size: u64) -> anyhow::Result<ReleaseReport> {
+        ReleaseReport::new(
+            &self.base_version,
+            &self.new_version,
+            &self.manifest,
+            self.base,
+            self.new,
+            &self.patch_dir(),
+            download_size,
+        )
+    }
+}
+
+/// Directory the release tar at `out` is written to.
+pub fn out_dir(out: &Path) -> &Path {
+    match out.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+/// Time a release is dated at: midnight (UTC) of `date` if given, else
+/// `source_date_epoch` (the value of `SOURCE_DATE_EPOCH`) if set, else
+/// midnight of today. Only today is truncated to midnight, so that two runs on
+/// the same day generate the same release. `SOURCE_DATE_EPOCH` is already
+/// fixed by whoever sets it and is used as is, the manifest records its date.
+pub fn release_time(
+    date: Option<&str>,
+    source_date_epoch: Option<&str>,
+) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+    let midnight = |date: chrono::NaiveDate| date.and_time(chrono::NaiveTime::MIN).and_utc();
+    if let Some(date) = date {
+        let date = chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+            .with_context(|| format!("Invalid release date: {date}"))?;
+        return Ok(midnight(date));
+    }
+    match source_date_epoch {
+        Some(epoch) => epoch
+            .parse::<i64>()
+            .ok()
+            .filter(|epoch| *epoch >= 0)
+            .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+            .with_context(|| format!("Invalid {SOURCE_DATE_EPOCH}: {epoch}")),
+        None => Ok(midnight(chrono::Utc::now().date_naive())),
+    }
+}
+
+/// Compresses the payloads of the `actions` in `patch_dir` in place, keeping
+/// only those that get smaller. Returns the codec of every compressed
+/// payload.
+fn compress_payloads(
+    codec: Compression,
+    patch_dir: &Path,
+    actions: &[Action],
+) -> anyhow::Result<BTreeMap<String, Compression>> {
+    actions
+        .par_iter()
+        .filter_map(Action::payload)
+        .map(|name| {
+            let path = patch_dir.join(name);
+            let payload =
+                std::fs::read(&path).with_context(|| format!("Reading {}", path.display()))?;
+            let compressed = codec
+                .compress(&payload)
+                .with_context(|| format!("Compressing {}", path.display()))?;
+            if compressed.len() >= payload.len() {
+                return Ok(None);
+            }
+            std::fs::write(&path, compressed)
+                .with_context(|| format!("Writing {}", path.display()))?;
+            Ok(Some((name.to_string(), codec)))
+        })
+        .filter_map(Result::transpose)
+        .collect()
+}
+
+/// Actions recreating the links of `new` and the executable bits of its
+/// files.
+struct MetadataActions {
+    /// Links and executable bits of files that differ from `base`.
+    changed: Vec<Action>,
+    /// `set-mode` actions of the executable files that are not in `base`.
+    added_executables: Vec<Action>,
+}
+
+fn metadata_actions(base: &TreeSnapshot, new: &TreeSnapshot) -> anyhow::Result<MetadataActions> {
+    let mut metadata = MetadataActions {
+        changed: vec![],
+        added_executables: vec![],
+    };
+
+    for link in &base.links {
+        if new.link(&link.path) != Some(link) {
+            metadata.changed.push(Action::Delete {
+                path: path_to_string(&link.path)?,
+            });
+        }
+    }
+    for link in &new.links {
+        if base.link(&link.path) != Some(link) {
+            metadata.changed.push(Action::Symlink {
+                path: path_to_string(&link.path)?,
+                target: path_to_string(&link.target)?,
+            });
+        }
+    }
+    for file in &new.files {
+        let set_mode = || {
+            anyhow::Ok(Action::SetMode {
+                path: path_to_string(&file.path)?,
+                mode: release_mode(file).to_string(),
+            })
+        };
+        match base.get(&file.path) {
+            Some(base_file) if base_file.is_executable() != file.is_executable() => {
+                metadata.changed.push(set_mode()?);
+            }
+            None if file.is_executable() => metadata.added_executables.push(set_mode()?),
+            _ => {}
+        }
+    }
+
+    Ok(metadata)
+}
+
+/// Mode of `file` in `set-mode` actions. Only the executable bit is carried
+/// over, the other bits depend on the umask of whoever checked out the tree.
+fn release_mode(file: &TreeFile) -> &'static str {
+    if file.is_executable() { "0755" } else { "0644" }
+}
+
+/// Like [`tar::Builder::append_dir_all`], but appends the entries in sorted
+/// order so that the resulting archive does not depend on the order in which
+/// the file system returns directory entries. Every entry is timestamped with
+/// `mtime`.
+fn append_dir_all_sorted<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &Path,
+    dir: &Path,
+    mtime: u64,
+) -> anyhow::Result<()> {
+    let mut header = tar_header(dir, mtime)?;
+    tar.append_data(&mut header, name, io::empty())
+        .with_context(|| format!("Adding dir: {}", dir.display()))?;
+
+    for entry in sorted_dir_entries(dir)? {
+        let entry_name = name.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            append_dir_all_sorted(tar, &entry_name, &entry.path(), mtime)?;
+        } else {
+            append_file(tar, &entry_name, &entry.path(), mtime)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Appends the file at `path` as `name`, timestamped with `mtime`.
+fn append_file<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &Path,
+    path: &Path,
+    mtime: u64,
+) -> anyhow::Result<()> {
+    let mut header = tar_header(path, mtime)?;
+    let file = File::open(path).with_context(|| format!("Opening file: {}", path.display()))?;
+    tar.append_data(&mut header, name, file)
+        .with_context(|| format!("Adding file: {}", path.display()))
+}
+
+/// Tar header for the file or dir at `path`, with the owner and mode
+/// normalized like [`tar::HeaderMode::Deterministic`] but timestamped with
+/// `mtime`.
+fn tar_header(path: &Path, mtime: u64) -> anyhow::Result<tar::Header> {
+    let metadata =
+        std::fs::metadata(path).with_context(|| format!("Reading {}", path.display()))?;
+    let mut header = tar::Header::new_gnu();
+    header.set_metadata_in_mode(&metadata, tar::HeaderMode::Deterministic);
+    header.set_mtime(mtime);
+    Ok(header)
+}
+
+/// Reads the directory entries sorted by file name.
+fn sorted_dir_entries(dir: &Path) -> anyhow::Result<Vec<DirEntry>> {
+    let mut entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Reading dir: {}", dir.display()))?
+        .collect::<Result<Vec<_>, _>>()
+        .with_context(|| format!("Reading entries of dir: {}", dir.display()))?;
+    entries.sort_by_key(DirEntry::file_name);
+    Ok(entries)
+}
@@ -1,23 +1,183 @@
 // SPDX-FileCopyrightText: 2023 Foundation Devices, Inc. <hello@foundation.xyz>
 // SPDX-License-Identifier: GPL-3.0-or-later
 
-use serde::{Deserialize, Serialize};
+use {
+    anyhow::Context,
+    schemars::JsonSchema,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::BTreeMap,
+        path::{Component, Path},
+    },
+};
+
+/// Newest `manifest.json` format version, emitted by default.
+///
+/// Older parsers reject manifests with unknown fields, so every change to the
+/// format bumps this version and adds a step to [`downgrade`] that converts a
+/// manifest of the new version to the previous one.
+pub const FORMAT_VERSION: u32 = 9;
+/// Oldest `manifest.json` format version that can still be emitted.
+pub const MIN_FORMAT_VERSION: u32 = 1;
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Contents of the `manifest.json` inside of a release tar.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "kebab-case", deny_unknown_fields)]
 pub struct ReleaseManifest {
-    pub label: String,
+    /// Version of the manifest format. Manifests without it are version 1.
+    #[serde(default = "format_version_1")]
+    pub format_version: u32,
+    /// Label of the release shown to the user.
+    pub label: Label,
+    /// Release notes in Markdown, keyed by language code.
+    #[serde(default, skip_serializing_if = "LocalizedText::is_empty")]
+    pub notes: LocalizedText,
+    /// Whether the user has to install the release.
     pub mandatory: bool,
+    /// Whether the release takes the device back to an older version. The
+    /// device and the signing tools apply a stricter policy to rollbacks.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub rollback: bool,
+    /// Release date, as `YYYY-MM-DD`.
     pub date: String,
+    /// Commits of the repository the trees were read from.
+    #[serde(default, skip_serializing_if = "Commits::is_empty")]
+    pub commits: Commits,
+    /// Conditions the device has to meet to install the release.
+    #[serde(default, skip_serializing_if = "Constraints::is_empty")]
+    pub constraints: Constraints,
+    /// Codec of the payloads under `patch/` that are stored compressed, keyed
+    /// by the same names the actions use. The other payloads are stored as
+    /// is.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub compression: BTreeMap<String, Compression>,
+    /// Actions to perform, in order.
     pub actions: Vec<Action>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// Text in several languages, keyed by language code (e.g. `en`), in the
+/// same format as the app names in the app manifests.
+pub type LocalizedText = BTreeMap<String, String>;
+
+/// Language of the text used when only a single language can be shown.
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+/// Label of a release, either a single text or a text per language.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Label {
+    Text(String),
+    Localized(LocalizedText),
+}
+
+impl Label {
+    /// Returns the label in the [`DEFAULT_LANGUAGE`], or in any language if
+    /// there is none in the default one.
+    pub fn text(&self) -> Option<&str> {
+        match self {
+            Label::Text(text) => Some(text),
+            Label::Localized(texts) => texts
+                .get(DEFAULT_LANGUAGE)
+                .or_else(|| texts.values().next())
+                .map(String::as_str),
+        }
+    }
+}
+
+/// Codec a payload is compressed with. Only codecs the device can decode are
+/// supported.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::ValueEnum,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    /// Zstandard.
+    Zstd,
+    /// XZ (LZMA2).
+    Xz,
+}
+
+/// Commits of the repository the base and the new tree of a release were
+/// read from, as full commit IDs.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Commits {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub base: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub new: Option<String>,
+}
+
+impl Commits {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+}
+
+/// Conditions the device has to meet to install a release. Unset conditions
+/// always hold.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema, clap::Args)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Constraints {
+    /// Version the release can be installed on. Can be repeated.
+    #[arg(long = "from-version", value_name = "VERSION")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub from_versions: Vec<String>,
+    /// Hardware revision the release can be installed on. Can be repeated.
+    #[arg(long = "hardware-revision", value_name = "REVISION")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hardware_revisions: Vec<String>,
+    /// Minimum bootloader version required by the release.
+    #[arg(long, value_name = "VERSION")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_bootloader_version: Option<String>,
+    /// Last day the release can be installed on, as `YYYY-MM-DD`.
+    #[arg(long, value_name = "DATE", value_parser = parse_date)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires: Option<String>,
+}
+
+impl Constraints {
+    pub fn is_empty(&self) -> bool {
+        self == &Self::default()
+    }
+
+    /// Fills the unset conditions from `other`.
+    pub fn or(mut self, other: &Self) -> Self {
+        if self.from_versions.is_empty() {
+            self.from_versions.clone_from(&other.from_versions);
+        }
+        if self.hardware_revisions.is_empty() {
+            self.hardware_revisions
+                .clone_from(&other.hardware_revisions);
+        }
+        if self.min_bootloader_version.is_none() {
+            self.min_bootloader_version
+                .clone_from(&other.min_bootloader_version);
+        }
+        if self.expires.is_none() {
+            self.expires.clone_from(&other.expires);
+        }
+        self
+    }
+}
+
+/// Parses a `YYYY-MM-DD` date.
+pub fn parse_date(date: &str) -> Result<String, String> {
+    chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map(|date| date.to_string())
+        .map_err(|err| format!("expected a YYYY-MM-DD date: {err}"))
+}
+
+/// A single step of the update. Paths are relative to the root of the KeyOS
+/// file system, `source` of `add`/`replace` and `patch-file` are relative to
+/// the `patch/` directory of the release tar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "action", rename_all = "kebab-case", deny_unknown_fields)]
 pub enum Action {
-    Transaction {
-        actions: Vec<Action>,
-    },
+    /// Actions that are applied all together or not at all.
+    Transaction { actions: Vec<Action> },
+    /// Patch `patch-source` in place with `patch-file`.
     #[serde(rename_all = "kebab-case")]
     Patch {
         patch_file: String,
@@ -25,6 +185,7 @@ pub enum Action {
         base_version: String,
         new_version: String,
     },
+    /// Patch `patch-source` with `patch-file` and write the result to `dest`.
     #[serde(rename_all = "kebab-case")]
     PatchAdd {
         patch_file: String,
@@ -33,39 +194,344 @@ pub enum Action {
         base_version: String,
         new_version: String,
     },
-    Add {
-        source: String,
-        dest: String,
+    /// Patch the window of `patch-source` at `offset` in place with
+    /// `patch-file`. Large files are patched in several chunks, in order of
+    /// their offset, so that only one window has to be in memory at a time.
+    ///
+    /// A chunk whose window already hashes to `new-sha256` was applied before
+    /// and is skipped, so an interrupted update can be resumed. The file is
+    /// truncated to `file-size` by the chunk whose window ends there, which
+    /// is always the last one.
+    #[serde(rename_all = "kebab-case")]
+    PatchChunk {
+        patch_file: String,
+        patch_source: String,
+        base_version: String,
+        new_version: String,
+        /// Offset of the window in the file, in bytes.
+        offset: u64,
+        /// Length of the window before the patch.
+        base_length: u64,
+        /// Hex encoded SHA-256 of the window before the patch.
+        base_sha256: String,
+        /// Length of the window after the patch.
+        new_length: u64,
+        /// Hex encoded SHA-256 of the window after the patch.
+        new_sha256: String,
+        /// Size of the whole file after all of its chunks are applied.
+        file_size: u64,
     },
+    /// Add the new file `dest` with the contents of `source`.
+    Add { source: String, dest: String },
+    /// Overwrite `dest` with the contents of `source`.
     #[serde(rename_all = "kebab-case")]
     Replace {
         source: String,
         dest: String,
         new_version: String,
     },
+    /// Update the firmware of the Bluetooth controller.
     UpdateBt,
-    Delete {
+    /// Delete the file at `path`.
+    Delete { path: String },
+    /// Rename `source` to `dest`.
+    Rename { source: String, dest: String },
+    /// Move `source` to `dest`.
+    Move { source: String, dest: String },
+    /// Copy `source` to `dest`.
+    Copy { source: String, dest: String },
+    /// Create a symbolic link at `path` pointing to `target`.
+    Symlink { path: String, target: String },
+    /// Set the permission bits of `path` to `mode`, in octal (e.g. `0775`).
+    SetMode {
         path: String,
+        #[schemars(regex(pattern = r"^0[0-7]{3}$"))]
+        mode: String,
     },
-    Rename {
-        source: String,
-        dest: String,
-    },
-    Move {
-        source: String,
-        dest: String,
-    },
-    Copy {
-        source: String,
-        dest: String,
-    },
-    Set {
-        setting: String,
-        value: String,
-    },
+    /// Set `setting` to `value`.
+    Set { setting: String, value: String },
+    /// Open the app with `app-id` at `route` after the update.
     #[serde(rename_all = "kebab-case")]
-    OpenApp {
-        app_id: String,
-        route: String,
-    },
+    OpenApp { app_id: String, route: String },
+}
+
+/// Whether `path` is a relative path made of plain names only.
+fn is_tree_path(path: &str) -> bool {
+    !path.is_empty()
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Whether the symlink at `path` resolves to `target` without going above the
+/// root of the tree. Symlinks already in the tree are not followed.
+fn link_stays_in_tree(path: &str, target: &str) -> bool {
+    let mut depth = Path::new(path).components().count() - 1;
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir => match depth.checked_sub(1) {
+                Some(parent) => depth = parent,
+                None => return false,
+            },
+            Component::RootDir | Component::Prefix(_) => return false,
+        }
+    }
+    true
+}
+
+fn format_version_1() -> u32 {
+    1
+}
+
+impl ReleaseManifest {
+    /// Deserializes a manifest of any supported format version.
+    pub fn from_slice(manifest: &[u8]) -> anyhow::Result<Self> {
+        let value: serde_json::Value =
+            serde_json::from_slice(manifest).context("Parsing manifest.json")?;
+        let format_version = value
+            .get("format-version")
+            .map_or(Some(1), serde_json::Value::as_u64)
+            .context("format-version should be a number")?;
+        anyhow::ensure!(
+            (u64::from(MIN_FORMAT_VERSION)..=u64::from(FORMAT_VERSION)).contains(&format_version),
+            "Unsupported manifest format version {format_version}, supported versions are \
+             {MIN_FORMAT_VERSION} to {FORMAT_VERSION}"
+        );
+        serde_json::from_value(value).context("Deserializing manifest.json")
+    }
+
+    /// Serializes the manifest in the given format version. The in-memory
+    /// manifest always uses the newest format, regardless of the version it
+    /// was read from.
+    ///
+    /// Fails if the manifest uses features that can't be expressed in that
+    /// version.
+    pub fn to_format_version(&self, format_version: u32) -> anyhow::Result<serde_json::Value> {
+        anyhow::ensure!(
+            (MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&format_version),
+            "Unsupported manifest format version {format_version}, supported versions are \
+             {MIN_FORMAT_VERSION} to {FORMAT_VERSION}"
+        );
+
+        let mut value = serde_json::to_value(self).expect("Serialization should not fail");
+        let manifest = value.as_object_mut().expect("Manifest should be an object");
+        manifest.insert(String::from("format-version"), FORMAT_VERSION.into());
+        for from in (format_version + 1..=FORMAT_VERSION).rev() {
+            downgrade(from, manifest)
+                .with_context(|| format!("Converting manifest to format version {}", from - 1))?;
+            if let Some(version) = manifest.get_mut("format-version") {
+                *version = (from - 1).into();
+            }
+        }
+        Ok(value)
+    }
+
+    /// Returns the base and the new version of the release, as recorded by its
+    /// first patch action.
+    pub fn versions(&self) -> Option<(&str, &str)> {
+        self.actions.iter().find_map(Action::versions)
+    }
+}
+
+impl Action {
+    /// Name of the action as it appears in the manifest.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Transaction { .. } => "transaction",
+            Action::Patch { .. } => "patch",
+            Action::PatchAdd { .. } => "patch-add",
+            Action::PatchChunk { .. } => "patch-chunk",
+            Action::Add { .. } => "add",
+            Action::Replace { .. } => "replace",
+            Action::UpdateBt => "update-bt",
+            Action::Delete { .. } => "delete",
+            Action::Rename { .. } => "rename",
+            Action::Move { .. } => "move",
+            Action::Copy { .. } => "copy",
+            Action::Symlink { .. } => "symlink",
+            Action::SetMode { .. } => "set-mode",
+            Action::Set { .. } => "set",
+            Action::OpenApp { .. } => "open-app",
+        }
+    }
+
+    /// Returns the first action matching `predicate`, searching transactions
+    /// too.
+    fn find(&self, predicate: &impl Fn(&Action) -> bool) -> Option<&Action> {
+        match self {
+            Action::Transaction { actions } => {
+                actions.iter().find_map(|action| action.find(predicate))
+            }
+            _ => predicate(self).then_some(self),
+        }
+    }
+
+    /// Name of the payload under `patch/` the action reads, if any.
+    pub fn payload(&self) -> Option<&str> {
+        match self {
+            Action::Patch { patch_file, .. }
+            | Action::PatchAdd { patch_file, .. }
+            | Action::PatchChunk { patch_file, .. } => Some(patch_file),
+            Action::Add { source, .. } | Action::Replace { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+
+    /// The first path of the tree the action touches that would leave it: an
+    /// absolute path, a path with a `..` component, or a symlink `target`
+    /// pointing above the root of the tree. Transactions are not searched.
+    pub fn unsafe_path(&self) -> Option<&str> {
+        let paths: Vec<&str> = match self {
+            Action::Patch { patch_source, .. } | Action::PatchChunk { patch_source, .. } => {
+                vec![patch_source]
+            }
+            Action::PatchAdd {
+                patch_source, dest, ..
+            } => vec![patch_source, dest],
+            Action::Add { dest, .. } | Action::Replace { dest, .. } => vec![dest],
+            Action::Delete { path } | Action::SetMode { path, .. } => vec![path],
+            Action::Rename { source, dest }
+            | Action::Move { source, dest }
+            | Action::Copy { source, dest } => vec![source, dest],
+            Action::Symlink { path, target } => {
+                if !is_tree_path(path) {
+                    return Some(path);
+                }
+                return (!link_stays_in_tree(path, target)).then_some(target);
+            }
+            Action::Transaction { .. }
+            | Action::UpdateBt
+            | Action::Set { .. }
+            | Action::OpenApp { .. } => vec![],
+        };
+        paths.into_iter().find(|path| !is_tree_path(path))
+    }
+
+    fn versions(&self) -> Option<(&str, &str)> {
+        match self {
+            Action::Transaction { actions } => actions.iter().find_map(Action::versions),
+            Action::Patch {
+                base_version,
+                new_version,
+                ..
+            }
+            | Action::PatchAdd {
+                base_version,
+                new_version,
+                ..
+            }
+            | Action::PatchChunk {
+                base_version,
+                new_version,
+                ..
+            } => Some((base_version, new_version)),
+            _ => None,
+        }
+    }
+
+    /// Position of the action kind in the fixed order `release-gen` emits
+    /// actions in: deletes, then renames, then patches, then adds and links,
+    /// then mode changes of the files in place.
+    ///
+    /// Actions that are never generated from a tree diff are placed after all
+    /// of the above.
+    pub fn order(&self) -> u8 {
+        match self {
+            Action::Delete { .. } => 0,
+            Action::Rename { .. } | Action::Move { .. } => 1,
+            Action::Patch { .. } | Action::PatchAdd { .. } | Action::PatchChunk { .. } => 2,
+            Action::Add { .. }
+            | Action::Replace { .. }
+            | Action::Copy { .. }
+            | Action::Symlink { .. } => 3,
+            Action::SetMode { .. } => 4,
+            Action::Transaction { .. }
+            | Action::UpdateBt
+            | Action::Set { .. }
+            | Action::OpenApp { .. } => 5,
+        }
+    }
+}
+
+/// Sorts the actions by [`Action::order`]. The sort is stable, so actions of
+/// the same kind keep their (path sorted) order.
+pub fn sort_actions(actions: &mut [Action]) {
+    actions.sort_by_key(Action::order);
+}
+
+/// Converts a serialized manifest from format version `from` to `from - 1`.
+fn downgrade(
+    from: u32,
+    manifest: &mut serde_json::Map<String, serde_json::Value>,
+) -> anyhow::Result<()> {
+    match from {
+        // Version 1 has no `format-version` field.
+        2 => {
+            manifest.shift_remove("format-version");
+        }
+        // Version 2 has no constraints. Dropping them would make the release
+        // installable on devices it is not meant for.
+        3 => anyhow::ensure!(
+            !manifest.contains_key("constraints"),
+            "Constraints can't be expressed in format version 2"
+        ),
+        // Version 3 has no release notes and only a single label text.
+        4 => {
+            manifest.shift_remove("notes");
+            let label: Label =
+                serde_json::from_value(manifest["label"].clone()).context("Reading label")?;
+            manifest["label"] = label.text().context("Label has no text")?.into();
+        }
+        // Version 4 has no links or modes, and dropping them would leave the
+        // device with a broken tree.
+        5 => {
+            let metadata =
+                |action: &Action| matches!(action, Action::Symlink { .. } | Action::SetMode { .. });
+            if let Some(action) = find_action(manifest, metadata)? {
+                anyhow::bail!("`{}` can't be expressed in format version 4", action.name());
+            }
+        }
+        // Version 5 has no compressed payloads, the device would read the
+        // compressed bytes as the payload.
+        6 => anyhow::ensure!(
+            !manifest.contains_key("compression"),
+            "Compressed payloads can't be expressed in format version 5"
+        ),
+        // Version 6 has no chunked patches.
+        7 => {
+            let chunk = |action: &Action| matches!(action, Action::PatchChunk { .. });
+            if find_action(manifest, chunk)?.is_some() {
+                anyhow::bail!("`patch-chunk` can't be expressed in format version 6");
+            }
+        }
+        // Version 7 can't mark rollbacks, which would make them look like
+        // regular updates.
+        8 => anyhow::ensure!(
+            !manifest.contains_key("rollback"),
+            "Rollbacks can't be expressed in format version 7"
+        ),
+        // Version 8 has no commits. They are only there for traceability, so
+        // nothing changes for the device without them.
+        9 => {
+            manifest.shift_remove("commits");
+        }
+        _ => unreachable!("No format version {from}"),
+    }
+    Ok(())
+}
+
+/// Returns the first action of the serialized `manifest` matching
+/// `predicate`.
+fn find_action(
+    manifest: &serde_json::Map<String, serde_json::Value>,
+    predicate: impl Fn(&Action) -> bool,
+) -> anyhow::Result<Option<Action>> {
+    let actions: Vec<Action> =
+        serde_json::from_value(manifest["actions"].clone()).context("Reading actions")?;
+    Ok(actions
+        .iter()
+        .find_map(|action| action.find(&predicate))
+        .cloned())
 }
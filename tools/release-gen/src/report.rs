@@ -0,0 +1,313 @@
+use {
+    crate::{
+        error::GenerateError,
+        release_manifest::{Action, Compression, ReleaseManifest},
+        tree::TreeSnapshot,
+    },
+    anyhow::Context,
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        fmt::Write as _,
+        fs::File,
+        io::{self, Write as _},
+        path::{Path, PathBuf},
+    },
+};
+
+/// Format of the report written next to the release tar.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportFormat {
+    /// Markdown tables, e.g. for the release notes.
+    Markdown,
+    /// JSON, for other tools.
+    Json,
+}
+
+impl ReportFormat {
+    /// Path of the report of the release tar at `tar`.
+    pub fn path(self, tar: &Path) -> PathBuf {
+        tar.with_extension(match self {
+            ReportFormat::Markdown => "md",
+            ReportFormat::Json => "json",
+        })
+    }
+}
+
+/// What a release does and how much each part of it weighs.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ReleaseReport {
+    pub base_version: String,
+    pub new_version: String,
+    /// Size of the release tar.
+    pub download_size: u64,
+    /// Totals per top-level area of the tree, sorted by area.
+    pub areas: Vec<AreaTotals>,
+    /// Totals of all actions.
+    pub totals: AreaTotals,
+    /// Every action of the release, in order.
+    pub actions: Vec<ActionReport>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct ActionReport {
+    pub action: &'static str,
+    /// Path the action changes, or the setting or app of device actions.
+    pub path: Option<String>,
+    /// Top-level area of the tree the path is in, for file actions.
+    pub area: Option<String>,
+    pub base_size: Option<u64>,
+    pub new_size: Option<u64>,
+    /// Size of the patch or added file in the release.
+    pub payload_size: Option<u64>,
+    pub compression: Option<Compression>,
+    /// `payload-size` divided by `new-size`.
+    pub ratio: Option<f64>,
+}
+
+#[derive(Debug, Default, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct AreaTotals {
+    /// `app.bin`, `boot.bin`, `blassets`, `apps/<bundle>` and so on.
+    pub area: String,
+    pub actions: usize,
+    pub base_size: u64,
+    pub new_size: u64,
+    pub payload_size: u64,
+}
+
+impl AreaTotals {
+    fn add(&mut self, action: &ActionReport) {
+        self.actions += 1;
+        self.base_size += action.base_size.unwrap_or(0);
+        self.new_size += action.new_size.unwrap_or(0);
+        self.payload_size += action.payload_size.unwrap_or(0);
+    }
+}
+
+impl ReleaseReport {
+    /// Describes the release with the `manifest` generated from `base` to
+    /// `new`, with its payloads in `patch_dir` and a tar of `download_size`
+    /// bytes.
+    pub fn new(
+        base_version: &str,
+        new_version: &str,
+        manifest: &ReleaseManifest,
+        base: &TreeSnapshot,
+        new: &TreeSnapshot,
+        patch_dir: &Path,
+        download_size: u64,
+    ) -> anyhow::Result<Self> {
+        let mut actions = vec![];
+        for action in &manifest.actions {
+            match action {
+                Action::Transaction { actions: inner } => {
+                    for action in inner {
+                        actions.push(ActionReport::new(action, manifest, base, new, patch_dir)?);
+                    }
+                }
+                action => actions.push(ActionReport::new(action, manifest, base, new, patch_dir)?),
+            }
+        }
+
+        let mut areas = BTreeMap::<String, AreaTotals>::new();
+        let mut totals = AreaTotals {
+            area: String::from("total"),
+            ..Default::default()
+        };
+        for action in &actions {
+            totals.add(action);
+            if let Some(area) = &action.area {
+                areas
+                    .entry(area.clone())
+                    .or_insert_with(|| AreaTotals {
+                        area: area.clone(),
+                        ..Default::default()
+                    })
+                    .add(action);
+            }
+        }
+
+        Ok(Self {
+            base_version: base_version.to_string(),
+            new_version: new_version.to_string(),
+            download_size,
+            areas: areas.into_values().collect(),
+            totals,
+            actions,
+        })
+    }
+
+    /// Writes the report in `format` next to the release tar at `tar`. Like the
+    /// tar, an existing report is never overwritten.
+    pub fn write(&self, format: ReportFormat, tar: &Path) -> anyhow::Result<()> {
+        let path = format.path(tar);
+        let report = match format {
+            ReportFormat::Markdown => self.to_markdown(),
+            ReportFormat::Json => {
+                serde_json::to_string_pretty(self).expect("Serialization should not fail") + "\n"
+            }
+        };
+        let mut file = File::create_new(&path).map_err(|err| match err.kind() {
+            io::ErrorKind::AlreadyExists => GenerateError::ReportExists(path.clone()).into(),
+            _ => anyhow::Error::new(err).context(format!("Creating report: {}", path.display())),
+        })?;
+        file.write_all(report.as_bytes())
+            .with_context(|| format!("Writing report: {}", path.display()))
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut md = String::new();
+        let _ = writeln!(
+            md,
+            "# Release {} → {}\n\nDownload size: {}\n",
+            self.base_version,
+            self.new_version,
+            format_size(self.download_size)
+        );
+
+        md.push_str("## Areas\n\n");
+        md.push_str("| Area | Actions | Base size | New size | Payload size |\n");
+        md.push_str("| --- | ---: | ---: | ---: | ---: |\n");
+        let areas = self
+            .areas
+            .iter()
+            .map(|area| (format!("`{}`", area.area), area))
+            .chain([(String::from("**Total**"), &self.totals)]);
+        for (name, area) in areas {
+            let _ = writeln!(
+                md,
+                "| {name} | {} | {} | {} | {} |",
+                area.actions,
+                format_size(area.base_size),
+                format_size(area.new_size),
+                format_size(area.payload_size)
+            );
+        }
+
+        md.push_str("\n## Actions\n\n");
+        md.push_str(
+            "| Action | Path | Base size | New size | Payload size | Compression | Ratio |\n",
+        );
+        md.push_str("| --- | --- | ---: | ---: | ---: | --- | ---: |\n");
+        for action in &self.actions {
+            let size = |size: Option<u64>| size.map(format_size).unwrap_or_default();
+            let _ = writeln!(
+                md,
+                "| {} | {} | {} | {} | {} | {} | {} |",
+                action.action,
+                action
+                    .path
+                    .as_ref()
+                    .map(|path| format!("`{path}`"))
+                    .unwrap_or_default(),
+                size(action.base_size),
+                size(action.new_size),
+                size(action.payload_size),
+                action
+                    .compression
+                    .map(Compression::name)
+                    .unwrap_or_default(),
+                action
+                    .ratio
+                    .map(|ratio| format!("{:.1}%", ratio * 100.0))
+                    .unwrap_or_default()
+            );
+        }
+        md
+    }
+}
+
+impl ActionReport {
+    fn new(
+        action: &Action,
+        manifest: &ReleaseManifest,
+        base: &TreeSnapshot,
+        new: &TreeSnapshot,
+        patch_dir: &Path,
+    ) -> anyhow::Result<Self> {
+        let size = |tree: &TreeSnapshot, path: &str| tree.get(Path::new(path)).map(|f| f.size);
+        let (path, base_size, new_size) = match action {
+            Action::Patch { patch_source, .. }
+            | Action::Replace {
+                dest: patch_source, ..
+            } => (
+                Some(patch_source.clone()),
+                size(base, patch_source),
+                size(new, patch_source),
+            ),
+            Action::PatchAdd { dest, .. }
+            | Action::Add { dest, .. }
+            | Action::Copy { dest, .. } => (Some(dest.clone()), None, size(new, dest)),
+            Action::PatchChunk {
+                patch_source,
+                base_length,
+                new_length,
+                ..
+            } => (
+                Some(patch_source.clone()),
+                Some(*base_length),
+                Some(*new_length),
+            ),
+            Action::Delete { path } => (Some(path.clone()), size(base, path), None),
+            Action::Rename { dest, .. } | Action::Move { dest, .. } => {
+                (Some(dest.clone()), None, size(new, dest))
+            }
+            Action::Symlink { path, .. } | Action::SetMode { path, .. } => {
+                (Some(path.clone()), None, None)
+            }
+            Action::Set { setting, .. } => (Some(setting.clone()), None, None),
+            Action::OpenApp { app_id, .. } => (Some(app_id.clone()), None, None),
+            Action::Transaction { .. } | Action::UpdateBt => (None, None, None),
+        };
+
+        let payload = action.payload();
+        let payload_size = payload
+            .map(|name| {
+                let path = patch_dir.join(name);
+                std::fs::metadata(&path)
+                    .map(|metadata| metadata.len())
+                    .with_context(|| format!("Reading {}", path.display()))
+            })
+            .transpose()?;
+        let ratio = match (payload_size, new_size) {
+            (Some(payload), Some(new)) if new > 0 => Some(payload as f64 / new as f64),
+            _ => None,
+        };
+
+        // Device actions come after all file actions.
+        let is_file_action = action.order() < Action::UpdateBt.order();
+        Ok(Self {
+            action: action.name(),
+            area: path.as_deref().filter(|_| is_file_action).map(area),
+            path,
+            base_size,
+            new_size,
+            payload_size,
+            compression: payload.and_then(|name| manifest.compression.get(name).copied()),
+            ratio,
+        })
+    }
+}
+
+/// Top-level area of the tree `path` is in. Every app bundle is an area of
+/// its own.
+fn area(path: &str) -> String {
+    let mut components = path.split('/');
+    match (components.next(), components.next(), components.next()) {
+        (Some("apps"), Some(bundle), Some(_)) => format!("apps/{bundle}"),
+        (Some(first), ..) => first.to_string(),
+        _ => path.to_string(),
+    }
+}
+
+/// Formats `bytes` in B, KiB or MiB.
+fn format_size(bytes: u64) -> String {
+    match bytes {
+        0..1024 => format!("{bytes} B"),
+        1024..1_048_576 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+    }
+}
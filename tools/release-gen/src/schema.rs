@@ -0,0 +1,89 @@
+use {
+    crate::release_manifest::{FORMAT_VERSION, ReleaseManifest},
+    anyhow::Context,
+    serde_json::json,
+};
+
+/// Committed JSON Schemas of the older manifest format versions, which can't
+/// be generated from the current types anymore.
+const OLDER_SCHEMAS: &[(u32, &str)] = &[
+    (
+        1,
+        include_str!("../../../schemas/release-manifest.v1.schema.json"),
+    ),
+    (
+        2,
+        include_str!("../../../schemas/release-manifest.v2.schema.json"),
+    ),
+    (
+        3,
+        include_str!("../../../schemas/release-manifest.v3.schema.json"),
+    ),
+    (
+        4,
+        include_str!("../../../schemas/release-manifest.v4.schema.json"),
+    ),
+    (
+        5,
+        include_str!("../../../schemas/release-manifest.v5.schema.json"),
+    ),
+    (
+        6,
+        include_str!("../../../schemas/release-manifest.v6.schema.json"),
+    ),
+    (
+        7,
+        include_str!("../../../schemas/release-manifest.v7.schema.json"),
+    ),
+    (
+        8,
+        include_str!("../../../schemas/release-manifest.v8.schema.json"),
+    ),
+];
+
+/// Generates the JSON Schema of the newest release `manifest.json` format.
+pub fn release_manifest_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(ReleaseManifest);
+    schema.insert(
+        String::from("$id"),
+        json!(format!("urn:keyos:release-manifest:v{FORMAT_VERSION}")),
+    );
+    schema.to_value()
+}
+
+/// Returns the JSON Schema of the given release `manifest.json` format
+/// version.
+pub fn release_manifest_schema_for(format_version: u32) -> anyhow::Result<serde_json::Value> {
+    if format_version == FORMAT_VERSION {
+        return Ok(release_manifest_schema());
+    }
+    let (_, schema) = OLDER_SCHEMAS
+        .iter()
+        .find(|(version, _)| *version == format_version)
+        .with_context(|| format!("No schema for manifest format version {format_version}"))?;
+    Ok(serde_json::from_str(schema).expect("Committed schema should be valid JSON"))
+}
+
+/// Validates the JSON value against the schema. Returns a description of
+/// every violation.
+pub fn validate(schema: &serde_json::Value, value: &serde_json::Value) -> Vec<String> {
+    let validator = jsonschema::validator_for(schema).expect("Generated schema should be valid");
+    validator
+        .iter_errors(value)
+        .map(|err| format!("{}: {err}", err.instance_path()))
+        .collect()
+}
+
+/// Validates a release `manifest.json` against the JSON Schema of its format
+/// version. Returns the format version and a description of every violation.
+pub fn validate_manifest(manifest: &[u8]) -> anyhow::Result<(u32, Vec<String>)> {
+    let value: serde_json::Value = serde_json::from_slice(manifest).context("Parsing manifest")?;
+    let format_version = value
+        .get("format-version")
+        .map_or(Some(1), serde_json::Value::as_u64)
+        .context("format-version should be a number")?;
+    let format_version = u32::try_from(format_version)
+        .with_context(|| format!("Unsupported format version {format_version}"))?;
+    let schema = release_manifest_schema_for(format_version)?;
+    Ok((format_version, validate(&schema, &value)))
+}
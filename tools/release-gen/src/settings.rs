@@ -0,0 +1,26 @@
+use crate::release_manifest::Action;
+
+/// Adds a `set` action to `actions`, unless an earlier one already sets the
+/// same value. Fails if an earlier one sets a different value.
+pub fn push_set(actions: &mut Vec<Action>, setting: &str, value: &str) -> anyhow::Result<()> {
+    let existing = actions.iter().find_map(|action| match action {
+        Action::Set {
+            setting: existing,
+            value,
+        } if existing == setting => Some(value),
+        _ => None,
+    });
+    match existing {
+        Some(existing) if existing == value => Ok(()),
+        Some(existing) => {
+            anyhow::bail!("Conflicting values for setting {setting}: {existing:?} and {value:?}")
+        }
+        None => {
+            actions.push(Action::Set {
+                setting: setting.to_string(),
+                value: value.to_string(),
+            });
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,94 @@
+use {
+    super::release_options,
+    crate::{
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        release::ReleaseBuilder,
+        release_manifest::Action,
+        tree::TreeSnapshot,
+    },
+    std::path::Path,
+};
+
+/// Actions touching files outside of the tree, next to an `outside.txt` that
+/// is one level above it.
+fn escaping_actions(payload: &str, outside: &Path) -> Vec<Action> {
+    let outside = outside.to_str().unwrap().to_string();
+    vec![
+        Action::Add {
+            source: payload.to_string(),
+            dest: String::from("../added.txt"),
+        },
+        Action::Replace {
+            source: payload.to_string(),
+            dest: outside.clone(),
+            new_version: String::from("v0.0.2"),
+        },
+        Action::Delete {
+            path: String::from("dir1/../../outside.txt"),
+        },
+        Action::Rename {
+            source: String::from("file1.txt"),
+            dest: String::from("../renamed.txt"),
+        },
+        Action::SetMode {
+            path: outside,
+            mode: String::from("0777"),
+        },
+        Action::Symlink {
+            path: String::from("dir1/link"),
+            target: String::from("../../outside.txt"),
+        },
+        Action::Symlink {
+            path: String::from("link"),
+            target: String::from("/etc/passwd"),
+        },
+    ]
+}
+
+#[test]
+fn releases_leaving_the_tree_are_rejected() {
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+    ReleaseBuilder::new("v0.0.1", &base, "v0.0.2", &new)
+        .options(release_options())
+        .plan()
+        .unwrap()
+        .write(&out)
+        .unwrap();
+    let release = ReleaseArchive::open(&out).unwrap();
+    let payload = release.payloads.keys().next().unwrap();
+
+    let outside = out_dir.path().join("outside.txt");
+    std::fs::write(&outside, b"outside").unwrap();
+    let dir = out_dir.path().join("applied");
+    copy_tree(&base, &dir).unwrap();
+
+    for action in escaping_actions(payload, &outside) {
+        let mut escaping = ReleaseArchive::open(&out).unwrap();
+        // Nested in a transaction, after actions that would apply fine.
+        let Action::Transaction { actions } = &mut escaping.manifest.actions[0] else {
+            panic!("Expected a transaction");
+        };
+        actions.push(action.clone());
+
+        let err = apply_release(&escaping, &dir).unwrap_err();
+        assert!(
+            err.to_string().contains("leaves the tree"),
+            "{action:?}: {err}"
+        );
+        assert!(
+            TreeSnapshot::new(&dir).unwrap().same_as(&base),
+            "{action:?}"
+        );
+    }
+    assert_eq!(std::fs::read(&outside).unwrap(), b"outside");
+    let mut outside_files: Vec<_> = std::fs::read_dir(out_dir.path())
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    outside_files.sort();
+    assert_eq!(outside_files, ["applied", "outside.txt", "release.tar"]);
+}
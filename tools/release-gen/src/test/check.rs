@@ -0,0 +1,241 @@
+use {
+    crate::{
+        archive::ReleaseArchive,
+        check::{Issue, check_release, check_release_with_settings},
+        config::ReleaseConfig,
+    },
+    serde_json::json,
+    std::path::Path,
+};
+
+/// Builds a release tar in memory from the manifest actions and the names of
+/// the files under `patch/`.
+fn release(actions: serde_json::Value, payloads: &[&str]) -> anyhow::Result<ReleaseArchive> {
+    let manifest = json!({
+        "label": "test label",
+        "mandatory": false,
+        "date": "2025-01-01",
+        "actions": actions,
+    });
+    let manifest = serde_json::to_vec(&manifest).unwrap();
+
+    let mut tar = tar::Builder::new(vec![]);
+    for name in payloads {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(4);
+        tar.append_data(&mut header, format!("patch/{name}"), &b"data"[..])
+            .unwrap();
+    }
+    let mut header = tar::Header::new_gnu();
+    header.set_size(manifest.len() as u64);
+    tar.append_data(&mut header, "manifest.json", manifest.as_slice())
+        .unwrap();
+    let tar = tar.into_inner().unwrap();
+
+    ReleaseArchive::read(tar.as_slice())
+}
+
+fn patch(path: &str) -> serde_json::Value {
+    json!({
+        "action": "patch",
+        "patch-file": path,
+        "patch-source": path,
+        "base-version": "v0.0.1",
+        "new-version": "v0.0.2",
+    })
+}
+
+#[test]
+fn valid_release() {
+    let release = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "delete", "path": "old.txt" },
+                patch("a.txt"),
+                { "action": "add", "source": "b.txt", "dest": "b.txt" },
+            ],
+        }]),
+        &["a.txt", "b.txt"],
+    )
+    .unwrap();
+
+    assert_eq!(check_release(&release), []);
+}
+
+#[test]
+fn unknown_fields_are_rejected() {
+    let result = release(
+        json!([{ "action": "delete", "path": "a.txt", "extra": true }]),
+        &[],
+    );
+
+    assert!(result.is_err());
+}
+
+#[test]
+fn missing_and_unreferenced_payloads() {
+    let release = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                patch("a.txt"),
+                { "action": "add", "source": "b.txt", "dest": "b.txt" },
+            ],
+        }]),
+        &["a.txt", "c.txt"],
+    )
+    .unwrap();
+
+    assert_eq!(
+        check_release(&release),
+        [
+            Issue::MissingPayload {
+                action: "add",
+                name: String::from("b.txt"),
+            },
+            Issue::UnreferencedPayload {
+                name: String::from("c.txt"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn conflicting_actions() {
+    let release = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "delete", "path": "a.txt" },
+                patch("a.txt"),
+                { "action": "add", "source": "b.txt", "dest": "b.txt" },
+                { "action": "copy", "source": "c.txt", "dest": "b.txt" },
+            ],
+        }]),
+        &["a.txt", "b.txt"],
+    )
+    .unwrap();
+
+    assert_eq!(
+        check_release(&release),
+        [
+            Issue::UseAfterDelete {
+                action: "patch",
+                path: String::from("a.txt"),
+            },
+            Issue::ConflictingWrites {
+                path: String::from("b.txt"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn unsafe_paths() {
+    let release = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "add", "source": "a.txt", "dest": "../a.txt" },
+                { "action": "delete", "path": "/etc/passwd" },
+                { "action": "rename", "source": "b.txt", "dest": "dir/../../b.txt" },
+                { "action": "symlink", "path": "dir/link", "target": "../c.txt" },
+                { "action": "symlink", "path": "dir/up", "target": "../../c.txt" },
+            ],
+        }]),
+        &["a.txt"],
+    )
+    .unwrap();
+
+    let unsafe_path = |action, path: &str| Issue::UnsafePath {
+        action,
+        path: path.to_string(),
+    };
+    assert_eq!(
+        check_release(&release),
+        [
+            unsafe_path("add", "../a.txt"),
+            unsafe_path("delete", "/etc/passwd"),
+            unsafe_path("rename", "dir/../../b.txt"),
+            unsafe_path("symlink", "../../c.txt"),
+        ]
+    );
+}
+
+#[test]
+fn nested_transactions() {
+    let well_formed = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "delete", "path": "a.txt" },
+                {
+                    "action": "transaction",
+                    "actions": [patch("b.txt"), { "action": "add", "source": "c.txt", "dest": "c.txt" }],
+                },
+            ],
+        }]),
+        &["b.txt", "c.txt"],
+    )
+    .unwrap();
+    assert_eq!(check_release(&well_formed), []);
+
+    let malformed = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "delete", "path": "a.txt" },
+                {
+                    "action": "transaction",
+                    "actions": [patch("a.txt"), { "action": "transaction", "actions": [] }],
+                },
+            ],
+        }]),
+        &["a.txt"],
+    )
+    .unwrap();
+    assert_eq!(
+        check_release(&malformed),
+        [
+            Issue::UseAfterDelete {
+                action: "patch",
+                path: String::from("a.txt"),
+            },
+            Issue::EmptyTransaction,
+        ]
+    );
+}
+
+#[test]
+fn unknown_and_conflicting_settings() {
+    let release = release(
+        json!([{
+            "action": "transaction",
+            "actions": [
+                { "action": "set", "setting": "display.brightness", "value": "50" },
+                { "action": "set", "setting": "display.brightness", "value": "60" },
+                { "action": "set", "setting": "no.such.setting", "value": "1" },
+            ],
+        }]),
+        &[],
+    )
+    .unwrap();
+    let conflicting = || Issue::ConflictingSettings {
+        setting: String::from("display.brightness"),
+    };
+
+    // Setting names are only checked against a release config.
+    assert_eq!(check_release(&release), [conflicting()]);
+
+    let config = ReleaseConfig::load(Path::new("src/test/fixtures/migrations")).unwrap();
+    assert_eq!(
+        check_release_with_settings(&release, &config.settings.known),
+        [
+            conflicting(),
+            Issue::UnknownSetting {
+                setting: String::from("no.such.setting"),
+            },
+        ]
+    );
+}
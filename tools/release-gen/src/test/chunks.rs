@@ -0,0 +1,177 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::{Issue, check_release},
+        release_manifest::Action,
+        tree::TreeSnapshot,
+    },
+    std::{num::NonZeroU64, path::Path},
+};
+
+/// Deterministic bytes that don't compress or diff trivially.
+fn noise(len: usize, seed: u32) -> Vec<u8> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        })
+        .collect()
+}
+
+fn write_tree(dir: &Path, files: &[(&str, &[u8])]) {
+    std::fs::create_dir_all(dir).unwrap();
+    for (name, contents) in files {
+        std::fs::write(dir.join(name), contents).unwrap();
+    }
+}
+
+/// Generates a release from `base_files` to `new_files` in `dir`, with
+/// 1 KiB chunks.
+fn generate(
+    dir: &Path,
+    base_files: &[(&str, &[u8])],
+    new_files: &[(&str, &[u8])],
+) -> (TreeSnapshot, TreeSnapshot, ReleaseArchive) {
+    let (base, new, out) = (dir.join("base"), dir.join("new"), dir.join("release.tar"));
+    write_tree(&base, base_files);
+    write_tree(&new, new_files);
+    crate::generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: base.clone(),
+        new_version: String::from("v0.0.2"),
+        new: new.clone(),
+        git: Default::default(),
+        options: ReleaseOptions {
+            chunk_size: NonZeroU64::new(1024),
+            ..release_options()
+        },
+        out: out.clone(),
+    })
+    .unwrap();
+    (
+        TreeSnapshot::new(&base).unwrap(),
+        TreeSnapshot::new(&new).unwrap(),
+        ReleaseArchive::open(&out).unwrap(),
+    )
+}
+
+fn transaction(release: &ReleaseArchive) -> &[Action] {
+    let [Action::Transaction { actions }] = release.manifest.actions.as_slice() else {
+        panic!("Expected a single transaction action");
+    };
+    actions
+}
+
+#[test]
+fn large_files_are_patched_in_chunks() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_big = noise(3000, 1);
+    let mut new_big = base_big.clone();
+    new_big[1500] ^= 0xff;
+    new_big.extend(noise(500, 2));
+    let base_shrunk = noise(3000, 3);
+
+    let (base, new, release) = generate(
+        dir.path(),
+        &[
+            ("big.bin", &base_big),
+            ("shrunk.bin", &base_shrunk),
+            ("small.txt", b"a"),
+        ],
+        &[
+            ("big.bin", &new_big),
+            ("shrunk.bin", &base_shrunk[..1024]),
+            ("small.txt", b"b"),
+        ],
+    );
+
+    let chunks: Vec<_> = transaction(&release)
+        .iter()
+        .filter_map(|action| match action {
+            Action::PatchChunk {
+                patch_file,
+                offset,
+                new_length,
+                file_size,
+                ..
+            } => Some((patch_file.as_str(), *offset, *new_length, *file_size)),
+            _ => None,
+        })
+        .collect();
+    // The first window of `big.bin` is unchanged. The only window of
+    // `shrunk.bin` is unchanged too, but truncates the file.
+    assert_eq!(
+        chunks,
+        [
+            ("big.bin.chunks/0001", 1024, 1024, 3500),
+            ("big.bin.chunks/0002", 2048, 1024, 3500),
+            ("big.bin.chunks/0003", 3072, 428, 3500),
+            ("shrunk.bin.chunks/0000", 0, 1024, 1024),
+        ]
+    );
+    assert!(transaction(&release).iter().any(|action| matches!(
+        action,
+        Action::Patch { patch_file, .. } if patch_file == "small.txt"
+    )));
+    assert_eq!(check_release(&release), []);
+
+    let applied = dir.path().join("applied");
+    copy_tree(&base, &applied).unwrap();
+    apply_release(&release, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&new));
+
+    let mut swapped = release;
+    let Action::Transaction { actions } = &mut swapped.manifest.actions[0] else {
+        unreachable!();
+    };
+    let first = actions
+        .iter()
+        .position(|action| matches!(action, Action::PatchChunk { .. }))
+        .unwrap();
+    actions.swap(first, first + 1);
+    assert_eq!(
+        check_release(&swapped),
+        [Issue::InconsistentChunks {
+            path: String::from("big.bin")
+        }]
+    );
+}
+
+#[test]
+fn interrupted_chunks_resume() {
+    let dir = tempfile::tempdir().unwrap();
+    let base_big = noise(4096, 4);
+    let new_big = noise(4096, 5);
+    let (base, new, mut release) = generate(
+        dir.path(),
+        &[("big.bin", &base_big)],
+        &[("big.bin", &new_big)],
+    );
+    assert_eq!(transaction(&release).len(), 4);
+
+    let applied = dir.path().join("applied");
+    copy_tree(&base, &applied).unwrap();
+    let all = release.manifest.actions.clone();
+    let Action::Transaction { actions } = &mut release.manifest.actions[0] else {
+        unreachable!();
+    };
+    actions.truncate(2);
+    apply_release(&release, &applied).unwrap();
+    assert!(!TreeSnapshot::new(&applied).unwrap().same_as(&new));
+
+    // The chunks applied before are skipped.
+    release.manifest.actions = all;
+    apply_release(&release, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&new));
+    apply_release(&release, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&new));
+
+    // A window that is neither the base nor the new one is not patched.
+    std::fs::write(applied.join("big.bin"), noise(4096, 6)).unwrap();
+    assert!(apply_release(&release, &applied).is_err());
+}
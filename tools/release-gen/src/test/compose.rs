@@ -0,0 +1,150 @@
+use {
+    super::{release_options, updiff_path},
+    crate::{
+        GenerateRequest,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::check_release,
+        compose::{ComposeRequest, compose},
+        release_manifest::{Action, FORMAT_VERSION},
+        tree::TreeSnapshot,
+    },
+    std::path::PathBuf,
+};
+
+fn generate(base_version: &str, base: &str, new_version: &str, new: &str, out: PathBuf) {
+    crate::generate(GenerateRequest {
+        base_version: base_version.to_string(),
+        base: PathBuf::from(base),
+        new_version: new_version.to_string(),
+        new: PathBuf::from(new),
+        git: Default::default(),
+        options: release_options(),
+        out,
+    })
+    .unwrap();
+}
+
+#[test]
+fn compose_chained_releases() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-compose");
+    let first = out_dir.join("first/release.tar");
+    let second = out_dir.join("second/release.tar");
+    let composed = out_dir.join("composed/release.tar");
+
+    generate(
+        "v0.0.1",
+        "src/test/fixtures/base/",
+        "v0.0.2",
+        "src/test/fixtures/new/",
+        first.clone(),
+    );
+    generate(
+        "v0.0.2",
+        "src/test/fixtures/new/",
+        "v0.0.3",
+        "src/test/fixtures/base2/",
+        second.clone(),
+    );
+
+    compose(ComposeRequest {
+        base: PathBuf::from("src/test/fixtures/base/"),
+        releases: vec![first, second],
+        base_version: None,
+        new_version: None,
+        label: None,
+        out: composed.clone(),
+        updiff_path: updiff_path(),
+        format_version: FORMAT_VERSION,
+        patch_cache: None,
+        compression: None,
+        chunk_size: None,
+        report: vec![],
+        date: None,
+    })
+    .unwrap();
+
+    let release = ReleaseArchive::open(&composed).unwrap();
+    assert_eq!(check_release(&release), []);
+    assert_eq!(release.manifest.versions(), Some(("v0.0.1", "v0.0.3")));
+    let [Action::Transaction { actions }] = release.manifest.actions.as_slice() else {
+        panic!("Expected a single transaction action");
+    };
+    // `dir2/file3.txt` is added by the first release and deleted by the second
+    // one, and the two patches of `dir2/file1.txt` collapse into one. The
+    // `dir3/file2.txt` link is deleted by the second release.
+    assert_eq!(
+        actions,
+        &[
+            Action::Delete {
+                path: String::from("dir1/subdir1/file1.txt"),
+            },
+            Action::Delete {
+                path: String::from("dir1/subdir1/file2.txt"),
+            },
+            Action::Delete {
+                path: String::from("dir2/file2.txt"),
+            },
+            Action::Delete {
+                path: String::from("dir3/file2.txt"),
+            },
+            Action::Patch {
+                patch_file: String::from("dir2/file1.txt"),
+                patch_source: String::from("dir2/file1.txt"),
+                base_version: String::from("v0.0.1"),
+                new_version: String::from("v0.0.3"),
+            },
+        ]
+    );
+
+    let applied_dir = out_dir.join("applied");
+    let base = TreeSnapshot::new(&PathBuf::from("src/test/fixtures/base/")).unwrap();
+    copy_tree(&base, &applied_dir).unwrap();
+    apply_release(&release, &applied_dir).unwrap();
+    let applied = TreeSnapshot::new(&applied_dir).unwrap();
+    let expected = TreeSnapshot::new(&PathBuf::from("src/test/fixtures/base2/")).unwrap();
+    assert!(applied.same_as(&expected));
+
+    std::fs::remove_dir_all(out_dir).unwrap();
+}
+
+#[test]
+fn compose_rejects_broken_chain() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-compose-broken");
+    let first = out_dir.join("first/release.tar");
+    let second = out_dir.join("second/release.tar");
+
+    generate(
+        "v0.0.1",
+        "src/test/fixtures/base/",
+        "v0.0.2",
+        "src/test/fixtures/new/",
+        first.clone(),
+    );
+    generate(
+        "v0.0.5",
+        "src/test/fixtures/new/",
+        "v0.0.6",
+        "src/test/fixtures/base2/",
+        second.clone(),
+    );
+
+    let result = compose(ComposeRequest {
+        base: PathBuf::from("src/test/fixtures/base/"),
+        releases: vec![first, second],
+        base_version: None,
+        new_version: None,
+        label: None,
+        out: out_dir.join("composed/release.tar"),
+        updiff_path: updiff_path(),
+        format_version: FORMAT_VERSION,
+        patch_cache: None,
+        compression: None,
+        chunk_size: None,
+        report: vec![],
+        date: None,
+    });
+    std::fs::remove_dir_all(out_dir).unwrap();
+
+    assert!(result.is_err());
+}
@@ -0,0 +1,160 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::{Issue, check_release},
+        compression::MAX_WINDOW_LOG,
+        release_manifest::Compression,
+        tree::TreeSnapshot,
+    },
+    std::{
+        io::Write,
+        path::{Path, PathBuf},
+    },
+    xz2::stream::{Check, Filters, LzmaOptions, Stream},
+};
+
+fn generate(options: ReleaseOptions, out: &Path) -> anyhow::Result<ReleaseArchive> {
+    crate::generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options,
+        out: out.to_path_buf(),
+    })?;
+    ReleaseArchive::open(out)
+}
+
+#[test]
+fn compressed_releases_apply() {
+    for codec in [Compression::Zstd, Compression::Xz] {
+        let out_dir = tempfile::tempdir().unwrap();
+        let options = ReleaseOptions {
+            compression: Some(codec),
+            ..release_options()
+        };
+        let release = generate(options, &out_dir.path().join("release.tar")).unwrap();
+
+        // The patches compress well, the tiny added file doesn't.
+        assert_eq!(
+            release.manifest.compression.keys().collect::<Vec<_>>(),
+            ["dir2/file1.txt", "dir2/file2.txt"],
+            "{codec:?}"
+        );
+        assert!(release.manifest.compression.values().all(|c| *c == codec));
+        assert_eq!(check_release(&release), []);
+
+        let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+        let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+        let dir = out_dir.path().join("applied");
+        copy_tree(&base, &dir).unwrap();
+        apply_release(&release, &dir).unwrap();
+        assert!(TreeSnapshot::new(&dir).unwrap().same_as(&new), "{codec:?}");
+    }
+}
+
+#[test]
+fn broken_compressed_payloads_are_reported() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let options = ReleaseOptions {
+        compression: Some(Compression::Zstd),
+        ..release_options()
+    };
+    let mut release = generate(options, &out_dir.path().join("release.tar")).unwrap();
+
+    release
+        .payloads
+        .insert(String::from("dir2/file1.txt"), b"not zstd".to_vec());
+    release
+        .manifest
+        .compression
+        .insert(String::from("missing.txt"), Compression::Xz);
+
+    assert_eq!(
+        check_release(&release),
+        [
+            Issue::UndecodablePayload {
+                name: String::from("dir2/file1.txt"),
+                compression: Compression::Zstd,
+            },
+            Issue::MissingPayload {
+                action: "compression",
+                name: String::from("missing.txt"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn compression_needs_format_version_6() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let options = ReleaseOptions {
+        compression: Some(Compression::Xz),
+        format_version: 5,
+        ..release_options()
+    };
+
+    assert!(generate(options, &out_dir.path().join("release.tar")).is_err());
+    assert!(!out_dir.path().join("release.tar").exists());
+}
+
+#[test]
+fn payloads_fit_the_device_window() {
+    // Larger than the window, and not compressible to nothing.
+    let data: Vec<u8> = (0..4u32 << MAX_WINDOW_LOG)
+        .map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8)
+        .collect();
+    for codec in [Compression::Zstd, Compression::Xz] {
+        let compressed = codec.compress(&data).unwrap();
+        assert_eq!(codec.decompress(&compressed).unwrap(), data, "{codec:?}");
+    }
+
+    // zstd at level 19 uses an 8 MiB window by default.
+    let default_window = zstd::encode_all(&data[..], 19).unwrap();
+    assert!(Compression::Zstd.decompress(&default_window).is_err());
+}
+
+/// Compresses `data` with xz and an LZMA2 dictionary of `dict_size` bytes.
+fn xz_with_dictionary(data: &[u8], dict_size: u32) -> Vec<u8> {
+    let mut options = LzmaOptions::new_preset(6).unwrap();
+    options.dict_size(dict_size);
+    let stream = Stream::new_stream_encoder(Filters::new().lzma2(&options), Check::Crc64).unwrap();
+    let mut encoder = xz2::write::XzEncoder::new_stream(vec![], stream);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn xz_dictionaries_over_the_window_are_undecodable() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let options = ReleaseOptions {
+        compression: Some(Compression::Xz),
+        ..release_options()
+    };
+    let mut release = generate(options, &out_dir.path().join("release.tar")).unwrap();
+    let payload = release.payload("dir2/file1.txt").unwrap().into_owned();
+
+    let fitting = xz_with_dictionary(&payload, 1 << MAX_WINDOW_LOG);
+    assert_eq!(Compression::Xz.decompress(&fitting).unwrap(), payload);
+
+    // 1.5 MiB is the smallest dictionary LZMA2 can encode above 1 MiB.
+    for dict_size in [3 << (MAX_WINDOW_LOG - 1), 8 << MAX_WINDOW_LOG] {
+        release.payloads.insert(
+            String::from("dir2/file1.txt"),
+            xz_with_dictionary(&payload, dict_size),
+        );
+        assert_eq!(
+            check_release(&release),
+            [Issue::UndecodablePayload {
+                name: String::from("dir2/file1.txt"),
+                compression: Compression::Xz,
+            }],
+            "{dict_size}"
+        );
+    }
+}
@@ -0,0 +1,107 @@
+use {
+    crate::{
+        config::ReleaseConfig,
+        constraints::{DeviceState, Violation, compare_versions, evaluate},
+        release_manifest::{Constraints, ReleaseManifest},
+    },
+    serde_json::json,
+    std::cmp::Ordering,
+};
+
+fn constraints() -> Constraints {
+    Constraints {
+        from_versions: vec![String::from("0.9.0"), String::from("v0.9.1")],
+        hardware_revisions: vec![String::from("1.2")],
+        min_bootloader_version: Some(String::from("1.0.10")),
+        expires: Some(String::from("2026-01-01")),
+    }
+}
+
+#[test]
+fn satisfied_constraints() {
+    let device = DeviceState {
+        version: Some(String::from("v0.9.1")),
+        hardware_revision: Some(String::from("1.2")),
+        bootloader_version: Some(String::from("1.0.10")),
+        date: Some(String::from("2026-01-01")),
+    };
+
+    assert_eq!(evaluate(&constraints(), &device), []);
+    assert_eq!(evaluate(&Constraints::default(), &device), []);
+    assert_eq!(evaluate(&constraints(), &DeviceState::default()), []);
+}
+
+#[test]
+fn violated_constraints() {
+    let device = DeviceState {
+        version: Some(String::from("0.8.0")),
+        hardware_revision: Some(String::from("1.1")),
+        bootloader_version: Some(String::from("1.0.9")),
+        date: Some(String::from("2026-01-02")),
+    };
+
+    assert_eq!(
+        evaluate(&constraints(), &device),
+        [
+            Violation::FromVersion {
+                version: String::from("0.8.0"),
+            },
+            Violation::HardwareRevision {
+                revision: String::from("1.1"),
+            },
+            Violation::BootloaderVersion {
+                version: String::from("1.0.9"),
+                min: String::from("1.0.10"),
+            },
+            Violation::Expired {
+                date: String::from("2026-01-02"),
+                expires: String::from("2026-01-01"),
+            },
+        ]
+    );
+}
+
+#[test]
+fn version_ordering() {
+    assert_eq!(compare_versions("1.0.10", "1.0.9"), Ordering::Greater);
+    assert_eq!(compare_versions("v1.0", "1.0.0"), Ordering::Equal);
+    assert_eq!(compare_versions("0.9.0", "1.0.0"), Ordering::Less);
+}
+
+#[test]
+fn constraints_need_format_version_3() {
+    let manifest = json!({
+        "format-version": 3,
+        "label": "test label",
+        "mandatory": false,
+        "date": "2025-01-01",
+        "constraints": { "from-versions": ["0.9.0"] },
+        "actions": [],
+    });
+    let manifest = ReleaseManifest::from_slice(manifest.to_string().as_bytes()).unwrap();
+
+    assert!(manifest.to_format_version(3).is_ok());
+    assert!(manifest.to_format_version(2).is_err());
+}
+
+#[test]
+fn config_expiry_dates_are_validated() {
+    let load = |expires: &str| {
+        let dir = tempfile::tempdir().unwrap();
+        let config =
+            format!("[release]\nversion = \"1.0.0\"\n\n[constraints]\nexpires = \"{expires}\"\n");
+        std::fs::write(dir.path().join("release-config.toml"), config).unwrap();
+        ReleaseConfig::load(dir.path()).map(|config| config.constraints.expires)
+    };
+
+    assert_eq!(load("2026-01-05").unwrap().as_deref(), Some("2026-01-05"));
+    // Normalized, so that it compares correctly with the device date.
+    assert_eq!(load("2026-1-5").unwrap().as_deref(), Some("2026-01-05"));
+    for malformed in ["2026-13-01", "05.01.2026", "tomorrow"] {
+        let err = load(malformed).unwrap_err();
+        assert!(
+            format!("{err:#}").contains("[constraints] expires"),
+            "{malformed}: {err:#}"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        ReleaseOptions,
+        archive::ReleaseArchive,
+        generate,
+        release::{ReleaseBuilder, release_time},
+        tree::TreeSnapshot,
+    },
+    std::path::{Path, PathBuf},
+};
+
+#[test]
+fn release_time_prefers_the_explicit_date() {
+    let time = |date, epoch| release_time(date, epoch).unwrap().to_rfc3339();
+
+    assert_eq!(
+        time(Some("2025-07-22"), Some("1700000000")),
+        "2025-07-22T00:00:00+00:00"
+    );
+    assert_eq!(time(None, Some("1700000000")), "2023-11-14T22:13:20+00:00");
+    assert_eq!(
+        release_time(None, None).unwrap().date_naive(),
+        chrono::Utc::now().date_naive()
+    );
+
+    for epoch in ["", "-1", "yesterday"] {
+        assert!(release_time(None, Some(epoch)).is_err(), "{epoch}");
+    }
+}
+
+#[test]
+fn tar_entries_are_timestamped_with_the_release_date() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+    generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options: ReleaseOptions {
+            date: Some(String::from("2024-02-29")),
+            ..release_options()
+        },
+        out: out.clone(),
+    })
+    .unwrap();
+
+    let midnight = release_time(Some("2024-02-29"), None).unwrap().timestamp();
+    let mut tar = tar::Archive::new(std::fs::File::open(&out).unwrap());
+    for entry in tar.entries().unwrap() {
+        let header = entry.unwrap().header().clone();
+        assert_eq!(header.mtime().unwrap(), midnight as u64);
+        assert_eq!(header.uid().unwrap(), 0);
+    }
+}
+
+#[test]
+fn source_date_epoch_dates_the_manifest_and_the_tar_entries() {
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+    ReleaseBuilder::new("v0.0.1", &base, "v0.0.2", &new)
+        .options(ReleaseOptions {
+            date: None,
+            ..release_options()
+        })
+        .source_date_epoch(Some(String::from("1700000000")))
+        .plan()
+        .unwrap()
+        .write(&out)
+        .unwrap();
+
+    // The epoch is not truncated, the manifest gets its date.
+    let release = ReleaseArchive::open(&out).unwrap();
+    assert_eq!(release.manifest.date, "2023-11-14");
+    let mut tar = tar::Archive::new(std::fs::File::open(&out).unwrap());
+    for entry in tar.entries().unwrap() {
+        assert_eq!(entry.unwrap().header().mtime().unwrap(), 1_700_000_000);
+    }
+}
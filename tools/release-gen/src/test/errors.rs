@@ -0,0 +1,164 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        ReleaseOptions,
+        error::GenerateError,
+        generate_release,
+        tree::TreeSnapshot,
+    },
+    std::{
+        ffi::OsStr,
+        os::unix::ffi::OsStrExt,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Names of the entries left in `dir`, sorted.
+fn entries(dir: &Path) -> Vec<String> {
+    let mut entries: Vec<_> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    entries
+}
+
+fn generate(base: &Path, new: &Path, options: ReleaseOptions, out: &Path) -> anyhow::Result<()> {
+    let base = TreeSnapshot::new(base).unwrap();
+    let new = TreeSnapshot::new(new).unwrap();
+    generate_release("v0.0.1", &base, "v0.0.2", &new, &options, vec![], out).map(drop)
+}
+
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from("src/test/fixtures").join(name)
+}
+
+#[test]
+fn non_utf8_paths_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (base, new, out) = (
+        dir.path().join("base"),
+        dir.path().join("new"),
+        dir.path().join("out"),
+    );
+    std::fs::create_dir_all(&base).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    let name = OsStr::from_bytes(b"invalid-\xff.txt");
+    std::fs::write(new.join(name), "data").unwrap();
+
+    let err = crate::generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base,
+        new_version: String::from("v0.0.2"),
+        new,
+        git: Default::default(),
+        options: release_options(),
+        out: out.join("release.tar"),
+    })
+    .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(GenerateError::NonUtf8Path(path)) if path == Path::new(name)
+    ));
+    assert_eq!(entries(&out), Vec::<String>::new());
+}
+
+#[test]
+fn existing_tar_is_kept() {
+    let out = tempfile::tempdir().unwrap();
+    let tar = out.path().join("release.tar");
+    std::fs::write(&tar, "old release").unwrap();
+
+    let err = generate(&fixture("base"), &fixture("new"), release_options(), &tar).unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(GenerateError::TarExists(_))
+    ));
+    assert_eq!(std::fs::read_to_string(&tar).unwrap(), "old release");
+    assert_eq!(entries(out.path()), ["release.tar"]);
+}
+
+#[test]
+fn files_next_to_the_tar_are_untouched() {
+    let out = tempfile::tempdir().unwrap();
+    std::fs::create_dir(out.path().join("patch")).unwrap();
+    std::fs::write(out.path().join("patch/unrelated.txt"), "data").unwrap();
+    std::fs::write(out.path().join("manifest.json"), "{}").unwrap();
+
+    generate(
+        &fixture("base"),
+        &fixture("new"),
+        release_options(),
+        &out.path().join("release.tar"),
+    )
+    .unwrap();
+
+    assert_eq!(
+        entries(out.path()),
+        ["manifest.json", "patch", "release.tar"]
+    );
+    assert_eq!(entries(&out.path().join("patch")), ["unrelated.txt"]);
+    assert_eq!(
+        std::fs::read_to_string(out.path().join("manifest.json")).unwrap(),
+        "{}"
+    );
+}
+
+#[test]
+fn vanished_source_files_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let (base, new, out) = (
+        dir.path().join("base"),
+        dir.path().join("new"),
+        dir.path().join("out"),
+    );
+    std::fs::create_dir_all(&base).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    std::fs::write(new.join("added.txt"), "data").unwrap();
+
+    let base = TreeSnapshot::new(&base).unwrap();
+    let new_snapshot = TreeSnapshot::new(&new).unwrap();
+    std::fs::remove_file(new.join("added.txt")).unwrap();
+    let err = generate_release(
+        "v0.0.1",
+        &base,
+        "v0.0.2",
+        &new_snapshot,
+        &release_options(),
+        vec![],
+        &out.join("release.tar"),
+    )
+    .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(GenerateError::SourceUnreadable { path, .. }) if path == Path::new("added.txt")
+    ));
+    assert_eq!(entries(&out), Vec::<String>::new());
+}
+
+#[test]
+fn updiff_failures_are_reported() {
+    let out = tempfile::tempdir().unwrap();
+    let options = ReleaseOptions {
+        updiff_path: PathBuf::from("false"),
+        ..release_options()
+    };
+
+    let err = generate(
+        &fixture("base"),
+        &fixture("new"),
+        options,
+        &out.path().join("release.tar"),
+    )
+    .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(GenerateError::UpdiffFailed { .. })
+    ));
+    assert_eq!(entries(out.path()), Vec::<String>::new());
+}
@@ -0,0 +1,74 @@
+use {
+    super::release_options,
+    crate::{GenerateRequest, ReleaseOptions, generate, tree::TreeSnapshot},
+    std::path::{Path, PathBuf},
+};
+
+const TREE: &str = "src/test/fixtures/filtered";
+
+fn snapshot(options: ReleaseOptions) -> (Vec<PathBuf>, Vec<PathBuf>) {
+    let snapshot =
+        TreeSnapshot::filtered(Path::new(TREE), &options.tree_filter().unwrap()).unwrap();
+    let files = snapshot.files.into_iter().map(|file| file.path).collect();
+    (files, snapshot.excluded)
+}
+
+fn paths(paths: &[&str]) -> Vec<PathBuf> {
+    paths.iter().map(PathBuf::from).collect()
+}
+
+#[test]
+fn default_excludes() {
+    let (files, excluded) = snapshot(release_options());
+
+    assert_eq!(files, paths(&["app.bin", "build/out.o", "sub/data.raw"]));
+    assert_eq!(
+        excluded,
+        paths(&[
+            ".DS_Store",
+            "README.md",
+            "app.bin~",
+            "docs/README.md",
+            "release-config.toml",
+            "release-notes.en.md",
+            "sub/.DS_Store",
+            "sub/data.raw.bak",
+        ])
+    );
+}
+
+#[test]
+fn exclude_and_include_patterns() {
+    let (files, excluded) = snapshot(ReleaseOptions {
+        exclude: vec![String::from("build/"), String::from("*.raw")],
+        include: vec![String::from("docs/README.md"), String::from("sub/*.raw")],
+        ..release_options()
+    });
+    assert_eq!(files, paths(&["app.bin", "docs/README.md", "sub/data.raw"]));
+    assert!(excluded.contains(&PathBuf::from("build")));
+
+    let (files, excluded) = snapshot(ReleaseOptions {
+        no_default_excludes: true,
+        ..release_options()
+    });
+    assert_eq!(files.len(), 11);
+    assert!(excluded.is_empty());
+}
+
+#[test]
+fn exclusions_are_in_the_build_report() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let report = generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from(TREE),
+        git: Default::default(),
+        options: release_options(),
+        out: out_dir.path().join("release.tar"),
+    })
+    .unwrap();
+
+    let (_, excluded) = snapshot(release_options());
+    assert_eq!(report.excluded[Path::new(TREE)], excluded);
+}
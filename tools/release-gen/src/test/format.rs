@@ -0,0 +1,93 @@
+use {
+    crate::{
+        release_manifest::{FORMAT_VERSION, MIN_FORMAT_VERSION, ReleaseManifest},
+        schema::{release_manifest_schema_for, validate},
+    },
+    serde_json::json,
+};
+
+/// Golden `manifest.json` of every supported format version.
+fn golden(format_version: u32) -> serde_json::Value {
+    let path = format!("src/test/fixtures/manifests/v{format_version}.json");
+    serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap()
+}
+
+#[test]
+fn golden_manifests_roundtrip() {
+    for format_version in MIN_FORMAT_VERSION..=FORMAT_VERSION {
+        let golden = golden(format_version);
+        let manifest = ReleaseManifest::from_slice(golden.to_string().as_bytes()).unwrap();
+
+        assert_eq!(
+            manifest.to_format_version(format_version).unwrap(),
+            golden,
+            "Format version {format_version}"
+        );
+    }
+}
+
+#[test]
+fn golden_manifests_match_their_schema() {
+    for format_version in MIN_FORMAT_VERSION..=FORMAT_VERSION {
+        let schema = release_manifest_schema_for(format_version).unwrap();
+
+        assert_eq!(
+            validate(&schema, &golden(format_version)),
+            Vec::<String>::new(),
+            "Format version {format_version}"
+        );
+    }
+}
+
+#[test]
+fn older_manifests_survive_upgrade() {
+    for format_version in MIN_FORMAT_VERSION..FORMAT_VERSION {
+        let golden = golden(format_version);
+        let manifest = ReleaseManifest::from_slice(golden.to_string().as_bytes()).unwrap();
+        let upgraded = manifest.to_format_version(FORMAT_VERSION).unwrap();
+        let upgraded = ReleaseManifest::from_slice(upgraded.to_string().as_bytes()).unwrap();
+
+        assert_eq!(
+            upgraded.to_format_version(format_version).unwrap(),
+            golden,
+            "Format version {format_version}"
+        );
+    }
+}
+
+#[test]
+fn unsupported_format_versions_are_rejected() {
+    let manifest = json!({
+        "format-version": FORMAT_VERSION + 1,
+        "label": "test label",
+        "mandatory": false,
+        "date": "2025-01-01",
+        "actions": [],
+    });
+
+    assert!(ReleaseManifest::from_slice(manifest.to_string().as_bytes()).is_err());
+    let manifest =
+        ReleaseManifest::from_slice(golden(FORMAT_VERSION).to_string().as_bytes()).unwrap();
+    assert!(manifest.to_format_version(FORMAT_VERSION + 1).is_err());
+    assert!(manifest.to_format_version(MIN_FORMAT_VERSION - 1).is_err());
+}
+
+#[test]
+fn manifest_fields_keep_their_order() {
+    let manifest = ReleaseManifest::from_slice(golden(1).to_string().as_bytes()).unwrap();
+    let keys = |format_version| {
+        let manifest = manifest.to_format_version(format_version).unwrap();
+        manifest
+            .as_object()
+            .unwrap()
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+
+    assert_eq!(keys(1), ["label", "mandatory", "date", "actions"]);
+    assert_eq!(
+        keys(FORMAT_VERSION),
+        ["format-version", "label", "mandatory", "date", "actions"]
+    );
+}
@@ -0,0 +1,131 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        generate,
+        git::{GitRefs, GitTree},
+        tree::TreeSnapshot,
+    },
+    std::{
+        path::{Path, PathBuf},
+        process::Command,
+    },
+};
+
+fn git(repo: &Path, args: &[&str]) -> String {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .args(["-c", "user.name=Test", "-c", "user.email=test@example.com"])
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "git {args:?} failed");
+    String::from_utf8(output.stdout).unwrap().trim().to_string()
+}
+
+/// Repository with the base fixture tagged `v0.0.1` and the new fixture
+/// tagged `v0.0.2`, both at `firmware/`.
+fn fixture_repo() -> tempfile::TempDir {
+    let repo = tempfile::tempdir().unwrap();
+    git(repo.path(), &["init", "--quiet"]);
+    for (version, fixture) in [("v0.0.1", "base"), ("v0.0.2", "new")] {
+        let tree = repo.path().join("firmware");
+        if tree.exists() {
+            std::fs::remove_dir_all(&tree).unwrap();
+        }
+        let fixture = TreeSnapshot::new(&Path::new("src/test/fixtures").join(fixture)).unwrap();
+        copy_tree(&fixture, &tree).unwrap();
+        git(repo.path(), &["add", "-A"]);
+        git(repo.path(), &["commit", "--quiet", "-m", version]);
+        git(repo.path(), &["tag", version]);
+    }
+    // The work tree must not be what the release is read from.
+    std::fs::remove_dir_all(repo.path().join("firmware")).unwrap();
+    repo
+}
+
+fn args(repo: &Path, base_ref: &str, out: PathBuf) -> GenerateRequest {
+    GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("firmware"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("firmware"),
+        git: GitRefs {
+            base_ref: Some(base_ref.to_string()),
+            new_ref: Some(String::from("v0.0.2")),
+            repo: repo.to_path_buf(),
+        },
+        options: release_options(),
+        out,
+    }
+}
+
+#[test]
+fn releases_are_generated_from_git_refs() {
+    let repo = fixture_repo();
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+    generate(args(repo.path(), "v0.0.1", out.clone())).unwrap();
+
+    let release = ReleaseArchive::open(&out).unwrap();
+    let commits = &release.manifest.commits;
+    assert_eq!(
+        commits.base.as_deref(),
+        Some(git(repo.path(), &["rev-parse", "v0.0.1^{commit}"]).as_str())
+    );
+    assert_eq!(
+        commits.new.as_deref(),
+        Some(git(repo.path(), &["rev-parse", "v0.0.2^{commit}"]).as_str())
+    );
+
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let applied = out_dir.path().join("applied");
+    copy_tree(&base, &applied).unwrap();
+    apply_release(&release, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&new));
+}
+
+#[test]
+fn unknown_refs_are_rejected() {
+    let repo = fixture_repo();
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+
+    let err = generate(args(repo.path(), "v0.0.0", out.clone())).unwrap_err();
+    assert!(format!("{err:#}").contains("Resolving git ref v0.0.0"));
+    assert!(!out.exists());
+}
+
+#[test]
+fn git_attributes_are_not_applied() {
+    let repo = tempfile::tempdir().unwrap();
+    git(repo.path(), &["init", "--quiet"]);
+    let tree = repo.path().join("firmware");
+    std::fs::create_dir(&tree).unwrap();
+    std::fs::write(
+        tree.join(".gitattributes"),
+        "ignored.txt export-ignore\nsubst.txt export-subst\n",
+    )
+    .unwrap();
+    std::fs::write(tree.join("ignored.txt"), "kept").unwrap();
+    std::fs::write(tree.join("subst.txt"), "$Format:%H$").unwrap();
+    git(repo.path(), &["add", "-A"]);
+    git(repo.path(), &["commit", "--quiet", "-m", "attributes"]);
+
+    let extracted = GitTree::extract(repo.path(), "HEAD", Path::new("firmware")).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(extracted.path().join("ignored.txt")).unwrap(),
+        "kept"
+    );
+    assert_eq!(
+        std::fs::read_to_string(extracted.path().join("subst.txt")).unwrap(),
+        "$Format:%H$"
+    );
+
+    let err = GitTree::extract(repo.path(), "HEAD", Path::new("missing")).unwrap_err();
+    assert!(err.to_string().contains("missing does not exist at HEAD"));
+}
@@ -0,0 +1,119 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        MetadataPolicy,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        release_manifest::Action,
+        tree::TreeSnapshot,
+    },
+    std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path},
+};
+
+fn generate(metadata_policy: MetadataPolicy, out: &Path) -> anyhow::Result<()> {
+    generate_from(
+        Path::new("src/test/fixtures/meta-base/"),
+        Path::new("src/test/fixtures/meta-new/"),
+        metadata_policy,
+        out,
+    )
+}
+
+fn generate_from(
+    base: &Path,
+    new: &Path,
+    metadata_policy: MetadataPolicy,
+    out: &Path,
+) -> anyhow::Result<()> {
+    crate::generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: base.to_path_buf(),
+        new_version: String::from("v0.0.2"),
+        new: new.to_path_buf(),
+        git: Default::default(),
+        options: ReleaseOptions {
+            metadata_policy: Some(metadata_policy),
+            ..release_options()
+        },
+        out: out.to_path_buf(),
+    })
+    .map(drop)
+}
+
+#[test]
+fn changed_links_and_modes_fail_by_default() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let err = generate(MetadataPolicy::Fail, &out_dir.path().join("release.tar")).unwrap_err();
+    let err = err.to_string();
+
+    for path in [
+        "apps/app/app.elf: mode 0755",
+        "apps/app/data: link to ../../a.txt",
+        "current: link removed",
+        "current: link to b.txt",
+        "old: link removed",
+    ] {
+        assert!(err.contains(path), "{path} not in: {err}");
+    }
+    // Added files are not a change of their mode.
+    assert!(!err.contains("apps/new-app/app.elf"), "{err}");
+}
+
+#[test]
+fn added_executables_keep_the_device_mode_by_default() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = dir.path().join("base");
+    let new = dir.path().join("new");
+    std::fs::create_dir_all(&base).unwrap();
+    std::fs::create_dir_all(&new).unwrap();
+    std::fs::write(new.join("app.elf"), "app").unwrap();
+    std::fs::set_permissions(new.join("app.elf"), Permissions::from_mode(0o775)).unwrap();
+
+    let tar_path = dir.path().join("release.tar");
+    generate_from(&base, &new, MetadataPolicy::Fail, &tar_path).unwrap();
+    let release = ReleaseArchive::open(&tar_path).unwrap();
+    let [Action::Transaction { actions }] = &release.manifest.actions[..] else {
+        panic!("Expected a single transaction action");
+    };
+    assert!(
+        !actions
+            .iter()
+            .any(|action| matches!(action, Action::SetMode { .. })),
+        "{actions:?}"
+    );
+}
+
+#[test]
+fn links_and_modes_as_actions() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let tar_path = out_dir.path().join("release.tar");
+    generate(MetadataPolicy::Actions, &tar_path).unwrap();
+
+    let release = ReleaseArchive::open(&tar_path).unwrap();
+    let [Action::Transaction { actions }] = &release.manifest.actions[..] else {
+        panic!("Expected a single transaction action");
+    };
+    // Modes are normalized to the executable bit.
+    for path in ["apps/app/app.elf", "apps/new-app/app.elf"] {
+        assert!(
+            actions.contains(&Action::SetMode {
+                path: String::from(path),
+                mode: String::from("0755"),
+            }),
+            "{path}"
+        );
+    }
+    assert!(actions.is_sorted_by_key(Action::order));
+
+    // Applying the release recreates the links and modes of the new tree.
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/meta-base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/meta-new/")).unwrap();
+    let dir = out_dir.path().join("applied");
+    copy_tree(&base, &dir).unwrap();
+    apply_release(&release, &dir).unwrap();
+    let applied = TreeSnapshot::new(&dir).unwrap();
+
+    assert!(applied.same_as(&new));
+}
@@ -0,0 +1,123 @@
+use {
+    crate::{
+        archive::ReleaseArchive,
+        compose::compose_device_actions,
+        config::ReleaseConfig,
+        release_manifest::{Action, Label, ReleaseManifest},
+    },
+    std::path::Path,
+};
+
+fn set(setting: &str, value: &str) -> Action {
+    Action::Set {
+        setting: setting.to_string(),
+        value: value.to_string(),
+    }
+}
+
+/// Writes `config` to a `release-config.toml` in a temporary version folder
+/// and loads it.
+fn load(config: &str) -> anyhow::Result<ReleaseConfig> {
+    let dir = tempfile::tempdir().unwrap();
+    std::fs::write(dir.path().join("release-config.toml"), config).unwrap();
+    ReleaseConfig::load(dir.path())
+}
+
+#[test]
+fn migrations_become_set_actions() {
+    let config = ReleaseConfig::load(Path::new("src/test/fixtures/migrations")).unwrap();
+
+    // Both migrations apply to 0.8.0 and agree on the brightness.
+    assert_eq!(
+        config.migration_actions("0.8.0").unwrap(),
+        [
+            set("display.brightness", "50"),
+            set("display.timeout", "30"),
+        ]
+    );
+    assert_eq!(
+        config.migration_actions("v0.9.0").unwrap(),
+        [set("display.brightness", "50")]
+    );
+}
+
+#[test]
+fn invalid_migrations_are_rejected() {
+    let unknown = load(
+        r#"
+        [release]
+        version = "1.0.0"
+
+        [settings]
+        known = ["display.brightness"]
+
+        [[migrations]]
+        settings = { "no.such.setting" = "1" }
+        "#,
+    );
+    assert!(unknown.is_err());
+
+    // Without `[settings]` no setting is known.
+    let undeclared = load(
+        r#"
+        [release]
+        version = "1.0.0"
+
+        [[migrations]]
+        settings = { "display.brightness" = "50" }
+        "#,
+    );
+    assert!(undeclared.is_err());
+
+    let conflicting = load(
+        r#"
+        [release]
+        version = "1.0.0"
+
+        [settings]
+        known = ["display.brightness"]
+
+        [[migrations]]
+        settings = { "display.brightness" = "50" }
+
+        [[migrations]]
+        settings = { "display.brightness" = "60" }
+        "#,
+    )
+    .unwrap();
+    assert!(conflicting.migration_actions("0.9.0").is_err());
+}
+
+#[test]
+fn composed_migrations_must_agree() {
+    let release = |actions: Vec<Action>| ReleaseArchive {
+        manifest: ReleaseManifest {
+            format_version: 1,
+            label: Label::Text(String::from("test label")),
+            notes: Default::default(),
+            mandatory: false,
+            rollback: false,
+            date: String::from("2025-01-01"),
+            commits: Default::default(),
+            constraints: Default::default(),
+            compression: Default::default(),
+            actions: vec![Action::Transaction { actions }],
+        },
+        payloads: Default::default(),
+    };
+
+    let agreeing = [
+        release(vec![set("display.brightness", "50"), Action::UpdateBt]),
+        release(vec![Action::UpdateBt, set("display.brightness", "50")]),
+    ];
+    assert_eq!(
+        compose_device_actions(&agreeing).unwrap(),
+        [set("display.brightness", "50"), Action::UpdateBt]
+    );
+
+    let conflicting = [
+        release(vec![set("display.brightness", "50")]),
+        release(vec![set("display.brightness", "60")]),
+    ];
+    assert!(compose_device_actions(&conflicting).is_err());
+}
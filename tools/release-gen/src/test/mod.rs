@@ -1,21 +1,83 @@
 use {
     crate::{
-        Args,
-        release_manifest::{Action, ReleaseManifest},
-        run,
+        FanOutRequest,
+        GenerateRequest,
+        MetadataPolicy,
+        ReleaseIndex,
+        ReleaseOptions,
+        archive::ReleaseArchive,
+        fan_out,
+        generate,
+        release_manifest::{Action, Constraints, FORMAT_VERSION, Label, ReleaseManifest},
     },
     std::{
         fs::File,
         io::{self, BufReader, Read, Seek},
+        num::NonZeroUsize,
         path::PathBuf,
     },
 };
 
+mod apply;
+mod check;
+mod chunks;
+mod compose;
+mod compression;
+mod constraints;
+mod date;
+mod errors;
+mod filter;
+mod format;
+mod git;
+mod metadata;
+mod migrations;
+mod notes;
+mod patch_cache;
+mod release;
+mod report;
+mod rollback;
+mod schema;
+mod tree;
+
+/// Path to the `updiff` tool, taken from the `UPDIFF_PATH` environment
+/// variable.
+fn updiff_path() -> PathBuf {
+    std::env::var("UPDIFF_PATH")
+        .expect("updiff path should exist")
+        .into()
+}
+
+/// Release options used by the tests.
+fn release_options() -> ReleaseOptions {
+    ReleaseOptions {
+        label: Some(String::from("test label")),
+        localized_label: Default::default(),
+        notes: Default::default(),
+        mandatory: false,
+        updiff_path: updiff_path(),
+        format_version: FORMAT_VERSION,
+        constraints: Constraints::default(),
+        bt_firmware: vec![],
+        open_app: None,
+        open_app_route: None,
+        exclude: vec![],
+        include: vec![],
+        no_default_excludes: false,
+        metadata_policy: Some(MetadataPolicy::Actions),
+        jobs: None,
+        patch_cache: None,
+        compression: None,
+        chunk_size: None,
+        report: vec![],
+        with_rollback: false,
+        is_rollback: false,
+        commits: Default::default(),
+        date: Some(String::from("2025-07-22")),
+    }
+}
+
 #[test]
 fn release_roundtrip() {
-    let updiff_path: PathBuf = std::env::var("UPDIFF_PATH")
-        .expect("updiff path should exist")
-        .into();
     let base_ver = String::from("v0.0.1");
     let base_dir = PathBuf::from("src/test/fixtures/base/");
     let new_ver = String::from("v0.0.2");
@@ -23,18 +85,20 @@ fn release_roundtrip() {
     let out_dir = PathBuf::from("src/test/fixtures/out");
     let tar_path = out_dir.join("release.tar");
 
-    let args = Args {
+    let args = GenerateRequest {
         base_version: base_ver.clone(),
         base: base_dir.clone(),
         new_version: new_ver.clone(),
         new: new_dir.clone(),
-        label: String::from("test label"),
-        mandatory: true,
+        git: Default::default(),
+        options: ReleaseOptions {
+            mandatory: true,
+            ..release_options()
+        },
         out: tar_path.clone(),
-        updiff_path,
     };
 
-    run(args).unwrap();
+    generate(args).unwrap();
 
     let tar_file = File::open(tar_path).unwrap();
     let mut tar = tar::Archive::new(tar_file);
@@ -44,12 +108,9 @@ fn release_roundtrip() {
     let reader = BufReader::new(manifest_file);
     let manifest: ReleaseManifest = serde_json::from_reader(reader).unwrap();
 
-    assert_eq!(manifest.label, "test label");
+    assert_eq!(manifest.label, Label::Text(String::from("test label")));
     assert!(manifest.mandatory);
-    assert_eq!(
-        manifest.date,
-        chrono::Utc::now().date_naive().to_string(),
-    );
+    assert_eq!(manifest.date, "2025-07-22");
 
     assert_eq!(manifest.actions.len(), 1);
 
@@ -57,6 +118,8 @@ fn release_roundtrip() {
         panic!("Expected a single transaction action");
     };
 
+    assert!(actions.is_sorted_by_key(Action::order));
+
     for action in actions {
         match action {
             Action::Patch {
@@ -123,3 +186,159 @@ fn release_roundtrip() {
 
     std::fs::remove_dir_all("src/test/fixtures/out").unwrap();
 }
+
+#[test]
+fn release_is_reproducible() {
+    let out_dirs = [
+        PathBuf::from("src/test/fixtures/out-repro-1"),
+        PathBuf::from("src/test/fixtures/out-repro-2"),
+    ];
+
+    // The number of patch workers doesn't change the result.
+    let jobs = [None, NonZeroUsize::new(1)];
+    let tars = std::array::from_fn::<_, 2, _>(|i| {
+        let tar_path = out_dirs[i].join("release.tar");
+        let args = GenerateRequest {
+            base_version: String::from("v0.0.1"),
+            base: PathBuf::from("src/test/fixtures/base/"),
+            new_version: String::from("v0.0.2"),
+            new: PathBuf::from("src/test/fixtures/new/"),
+            git: Default::default(),
+            options: ReleaseOptions {
+                jobs: jobs[i],
+                ..release_options()
+            },
+            out: tar_path.clone(),
+        };
+        generate(args).unwrap();
+        std::fs::read(tar_path).unwrap()
+    });
+
+    for out_dir in out_dirs {
+        std::fs::remove_dir_all(out_dir).unwrap();
+    }
+
+    assert_eq!(tars[0], tars[1]);
+}
+
+#[test]
+fn fan_out_index() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-fan-out");
+
+    let args = FanOutRequest {
+        new_version: String::from("v0.0.3"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        bases: vec![
+            (
+                String::from("v0.0.1"),
+                PathBuf::from("src/test/fixtures/base/"),
+            ),
+            (
+                String::from("v0.0.2"),
+                PathBuf::from("src/test/fixtures/base2/"),
+            ),
+        ],
+        options: release_options(),
+        out_dir: out_dir.clone(),
+    };
+
+    fan_out(args).unwrap();
+
+    let index_file = File::open(out_dir.join("index.json")).unwrap();
+    let index: ReleaseIndex = serde_json::from_reader(BufReader::new(index_file)).unwrap();
+
+    assert_eq!(index.new_version, "v0.0.3");
+    let base_versions: Vec<_> = index
+        .releases
+        .iter()
+        .map(|release| release.base_version.as_str())
+        .collect();
+    assert_eq!(base_versions, ["v0.0.1", "v0.0.2"]);
+
+    for release in &index.releases {
+        let tar = std::fs::read(out_dir.join(&release.file)).unwrap();
+        assert_eq!(
+            hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&tar)),
+            release.sha256
+        );
+    }
+
+    std::fs::remove_dir_all(out_dir).unwrap();
+}
+
+#[test]
+fn update_bt_follows_bt_firmware_changes() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-update-bt");
+    let transaction = |bt_firmware: &[&str], name: &str| {
+        let tar_path = out_dir.join(name).join("release.tar");
+        generate(GenerateRequest {
+            base_version: String::from("v0.0.1"),
+            base: PathBuf::from("src/test/fixtures/base/"),
+            new_version: String::from("v0.0.2"),
+            new: PathBuf::from("src/test/fixtures/new/"),
+            git: Default::default(),
+            options: ReleaseOptions {
+                bt_firmware: bt_firmware.iter().map(|p| p.to_string()).collect(),
+                ..release_options()
+            },
+            out: tar_path.clone(),
+        })
+        .unwrap();
+        let release = ReleaseArchive::open(&tar_path).unwrap();
+        let [Action::Transaction { actions }] = &release.manifest.actions[..] else {
+            panic!("Expected a single transaction action");
+        };
+        actions.clone()
+    };
+
+    // Changed and added files both count, and the update comes after the
+    // firmware is in place.
+    for (pattern, name) in [("dir2/file2.*", "changed"), ("**/file3.txt", "added")] {
+        let actions = transaction(&[pattern], name);
+        assert_eq!(actions.last(), Some(&Action::UpdateBt), "{pattern}");
+        assert_eq!(
+            actions.iter().filter(|a| **a == Action::UpdateBt).count(),
+            1
+        );
+    }
+
+    let actions = transaction(&["dir1/subdir2/*"], "unchanged");
+    assert!(!actions.contains(&Action::UpdateBt));
+
+    std::fs::remove_dir_all(out_dir).unwrap();
+}
+
+#[test]
+fn open_app_comes_last() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-open-app");
+    let generate = |app_id: &str, name: &str| {
+        let tar_path = out_dir.join(name).join("release.tar");
+        generate(GenerateRequest {
+            base_version: String::from("v0.0.1"),
+            base: PathBuf::from("src/test/fixtures/base/"),
+            new_version: String::from("v0.0.2"),
+            new: PathBuf::from("src/test/fixtures/apps/"),
+            git: Default::default(),
+            options: ReleaseOptions {
+                open_app: Some(app_id.to_string()),
+                open_app_route: Some(String::from("/changelog")),
+                ..release_options()
+            },
+            out: tar_path.clone(),
+        })
+        .map(|_| ReleaseArchive::open(&tar_path).unwrap())
+    };
+
+    let release = generate("0x53657474696E67730000000000000000", "settings").unwrap();
+    assert!(matches!(
+        release.manifest.actions[..],
+        [
+            Action::Transaction { .. },
+            Action::OpenApp { ref route, .. },
+        ] if route == "/changelog"
+    ));
+
+    assert!(generate("0x00", "missing").is_err());
+
+    std::fs::remove_dir_all(out_dir).unwrap();
+}
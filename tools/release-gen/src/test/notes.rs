@@ -0,0 +1,61 @@
+use {
+    super::release_options,
+    crate::{
+        config::ReleaseConfig,
+        release_manifest::{Label, LocalizedText, ReleaseManifest},
+    },
+    std::path::Path,
+};
+
+fn localized(texts: &[(&str, &str)]) -> LocalizedText {
+    texts
+        .iter()
+        .map(|(language, text)| (language.to_string(), text.to_string()))
+        .collect()
+}
+
+#[test]
+fn release_notes_are_read_from_config_dir() {
+    let config = ReleaseConfig::load(Path::new("src/test/fixtures/notes")).unwrap();
+
+    assert_eq!(
+        config.notes.labels,
+        localized(&[("de", "KeyOS 1.0 (de)"), ("en", "KeyOS 1.0")])
+    );
+    assert_eq!(
+        config.notes.notes,
+        localized(&[
+            ("de", "- Neue Seed-Vault-App\n- Schnellerer Start"),
+            ("en", "- New Seed Vault app\n- Faster boot"),
+        ])
+    );
+
+    let options = release_options();
+    let options = crate::ReleaseOptions {
+        label: None,
+        ..options
+    }
+    .with_config(Some(&config));
+    assert_eq!(
+        options.manifest_label(),
+        Label::Localized(config.notes.labels.clone())
+    );
+    assert_eq!(options.notes, config.notes.notes);
+
+    // `--label` takes precedence over the labels of the release notes.
+    let options = release_options().with_config(Some(&config));
+    assert_eq!(
+        options.manifest_label(),
+        Label::Text(String::from("test label"))
+    );
+}
+
+#[test]
+fn localized_label_downgrades_to_default_language() {
+    let golden = std::fs::read("src/test/fixtures/manifests/v4.json").unwrap();
+    let manifest = ReleaseManifest::from_slice(&golden).unwrap();
+    let manifest = manifest.to_format_version(3).unwrap();
+
+    assert_eq!(manifest["label"], "KeyOS Release");
+    assert!(manifest.get("notes").is_none());
+}
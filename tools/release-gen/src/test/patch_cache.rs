@@ -0,0 +1,100 @@
+use {
+    super::release_options,
+    crate::{
+        ReleaseOptions,
+        generate_release,
+        patch_cache::{CacheContents, LEFTOVER_MIN_AGE, PruneFilter, prune},
+        tree::TreeSnapshot,
+    },
+    std::{
+        fs::File,
+        path::{Path, PathBuf},
+        time::SystemTime,
+    },
+};
+
+fn generate(options: &ReleaseOptions, out: &Path) -> anyhow::Result<Vec<u8>> {
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    generate_release("v0.0.1", &base, "v0.0.2", &new, options, vec![], out)?;
+    Ok(std::fs::read(out).unwrap())
+}
+
+#[test]
+fn patches_are_reused() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let options = ReleaseOptions {
+        patch_cache: Some(cache_dir.clone()),
+        ..release_options()
+    };
+
+    let first = generate(&options, &dir.path().join("first/release.tar")).unwrap();
+    let contents = CacheContents::read(&cache_dir).unwrap();
+    assert_eq!(contents.entries.len(), 2);
+    for entry in &contents.entries {
+        entry.verify().unwrap();
+    }
+
+    // `updiff` is not needed anymore.
+    let cached_options = ReleaseOptions {
+        updiff_path: PathBuf::from("/nonexistent/updiff"),
+        ..options.clone()
+    };
+    let second = generate(&cached_options, &dir.path().join("second/release.tar")).unwrap();
+    assert_eq!(first, second);
+
+    // A broken patch is not reused.
+    let id = &contents.entries[0].id;
+    let patch = cache_dir
+        .join("v1")
+        .join(&id[..2])
+        .join(format!("{id}.patch"));
+    std::fs::write(&patch, b"broken").unwrap();
+    assert!(contents.entries[0].verify().is_err());
+    assert!(generate(&cached_options, &dir.path().join("third/release.tar")).is_err());
+    let fourth = generate(&options, &dir.path().join("fourth/release.tar")).unwrap();
+    assert_eq!(first, fourth);
+    contents.entries[0].verify().unwrap();
+}
+
+#[test]
+fn prune_removes_stale_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let cache_dir = dir.path().join("cache");
+    let options = ReleaseOptions {
+        patch_cache: Some(cache_dir.clone()),
+        ..release_options()
+    };
+    generate(&options, &dir.path().join("release.tar")).unwrap();
+
+    std::fs::create_dir_all(cache_dir.join("v0/ab")).unwrap();
+    let entry = &CacheContents::read(&cache_dir).unwrap().entries[0];
+    let shard = cache_dir.join("v1").join(&entry.id[..2]);
+    let leftover = shard.join(".tmpABCDEF");
+    std::fs::write(&leftover, b"partial").unwrap();
+    File::options()
+        .write(true)
+        .open(&leftover)
+        .unwrap()
+        .set_modified(SystemTime::now() - LEFTOVER_MIN_AGE)
+        .unwrap();
+    // May still be written by a concurrent run.
+    let in_progress = shard.join(".tmpGHIJKL");
+    std::fs::write(&in_progress, b"partial").unwrap();
+    // Not a shard.
+    std::fs::write(cache_dir.join("v1/.DS_Store"), b"").unwrap();
+
+    let contents = CacheContents::read(&cache_dir).unwrap();
+    assert_eq!(contents.other_versions, [cache_dir.join("v0")]);
+    assert_eq!(contents.leftovers, [leftover, in_progress.clone()]);
+
+    assert_eq!(prune(&cache_dir, PruneFilter::Stale).unwrap(), 0);
+    let contents = CacheContents::read(&cache_dir).unwrap();
+    assert_eq!(contents.entries.len(), 2);
+    assert!(contents.other_versions.is_empty());
+    assert_eq!(contents.leftovers, [in_progress]);
+
+    assert_eq!(prune(&cache_dir, PruneFilter::All).unwrap(), 2);
+    assert!(CacheContents::read(&cache_dir).unwrap().entries.is_empty());
+}
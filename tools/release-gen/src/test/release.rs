@@ -0,0 +1,45 @@
+use {
+    super::release_options,
+    crate::{
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::check_release,
+        release::ReleaseBuilder,
+        release_manifest::Action,
+        tree::TreeSnapshot,
+    },
+    std::path::Path,
+};
+
+#[test]
+fn planned_releases_are_written_and_read_back() {
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let mut plan = ReleaseBuilder::new("v0.0.1", &base, "v0.0.2", &new)
+        .options(release_options())
+        .plan()
+        .unwrap();
+
+    let [Action::Transaction { actions }] = plan.actions() else {
+        panic!("Unexpected actions: {:?}", plan.actions());
+    };
+    for payload in actions.iter().filter_map(Action::payload) {
+        assert!(plan.patch_dir().join(payload).is_file(), "{payload}");
+    }
+
+    let out_dir = tempfile::tempdir().unwrap();
+    let out = out_dir.path().join("release.tar");
+    plan.write(&out).unwrap();
+    let patch_dir = plan.patch_dir();
+    drop(plan);
+    assert!(!patch_dir.exists());
+
+    let release = ReleaseArchive::open(&out).unwrap();
+    assert_eq!(check_release(&release), []);
+    assert_eq!(release.manifest.versions(), Some(("v0.0.1", "v0.0.2")));
+
+    let applied = out_dir.path().join("applied");
+    copy_tree(&base, &applied).unwrap();
+    apply_release(&release, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&new));
+}
@@ -0,0 +1,133 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        ReleaseOptions,
+        error::GenerateError,
+        generate,
+        report::ReportFormat,
+    },
+    std::path::{Path, PathBuf},
+};
+
+fn write_tree(dir: &Path, files: &[(&str, &str)]) {
+    for (name, contents) in files {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, contents).unwrap();
+    }
+}
+
+#[test]
+fn report_has_totals_per_area() {
+    let dir = tempfile::tempdir().unwrap();
+    let (base, new, out) = (
+        dir.path().join("base"),
+        dir.path().join("new"),
+        dir.path().join("out/release.tar"),
+    );
+    write_tree(
+        &base,
+        &[
+            ("app.bin", "kernel v1"),
+            ("apps/gui-app-a/app.elf", "app a v1"),
+            ("apps/gui-app-a/icon.png", "icon"),
+            ("blassets/old.raw", "old asset"),
+        ],
+    );
+    write_tree(
+        &new,
+        &[
+            ("app.bin", "kernel v2 with more"),
+            ("apps/gui-app-a/app.elf", "app a v2"),
+            ("apps/gui-app-a/icon.png", "icon"),
+            ("apps/gui-app-b/app.elf", "app b"),
+            ("blassets/new.raw", "new asset"),
+        ],
+    );
+
+    generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base,
+        new_version: String::from("v0.0.2"),
+        new,
+        git: Default::default(),
+        options: ReleaseOptions {
+            report: vec![ReportFormat::Markdown, ReportFormat::Json],
+            ..release_options()
+        },
+        out: out.clone(),
+    })
+    .unwrap();
+
+    let report: serde_json::Value =
+        serde_json::from_slice(&std::fs::read(dir.path().join("out/release.json")).unwrap())
+            .unwrap();
+    assert_eq!(
+        report["download-size"],
+        std::fs::metadata(&out).unwrap().len()
+    );
+    let areas: Vec<_> = report["areas"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|area| {
+            (
+                area["area"].as_str().unwrap(),
+                area["actions"].as_u64().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(
+        areas,
+        [
+            ("app.bin", 1),
+            ("apps/gui-app-a", 1),
+            ("apps/gui-app-b", 1),
+            ("blassets", 2)
+        ]
+    );
+    let payloads: u64 = report["actions"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter_map(|action| action["payload-size"].as_u64())
+        .sum();
+    assert_eq!(report["totals"]["payload-size"], payloads);
+    assert_eq!(report["totals"]["actions"], 5);
+
+    let markdown = std::fs::read_to_string(dir.path().join("out/release.md")).unwrap();
+    assert!(markdown.starts_with("# Release v0.0.1 → v0.0.2\n"));
+    assert!(markdown.contains("| `apps/gui-app-b` | 1 | 0 B | 5 B | 5 B |"));
+    assert!(markdown.contains("| add | `apps/gui-app-b/app.elf` |  | 5 B | 5 B |  | 100.0% |"));
+}
+
+#[test]
+fn existing_reports_are_not_overwritten() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("release.tar");
+    let existing = dir.path().join("release.md");
+    std::fs::write(&existing, "notes").unwrap();
+
+    let err = generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options: ReleaseOptions {
+            report: vec![ReportFormat::Json, ReportFormat::Markdown],
+            ..release_options()
+        },
+        out: out.clone(),
+    })
+    .unwrap_err();
+
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(GenerateError::ReportExists(path)) if *path == existing
+    ));
+    assert_eq!(std::fs::read_to_string(&existing).unwrap(), "notes");
+    assert!(!out.exists());
+    assert!(!dir.path().join("release.json").exists());
+}
@@ -0,0 +1,107 @@
+use {
+    super::release_options,
+    crate::{
+        FanOutRequest,
+        GenerateRequest,
+        ReleaseIndex,
+        ReleaseOptions,
+        apply::{apply_release, copy_tree},
+        archive::ReleaseArchive,
+        check::check_release,
+        fan_out,
+        generate,
+        release_manifest::Label,
+        tree::TreeSnapshot,
+    },
+    std::path::{Path, PathBuf},
+};
+
+fn rollback_options() -> ReleaseOptions {
+    ReleaseOptions {
+        with_rollback: true,
+        mandatory: true,
+        ..release_options()
+    }
+}
+
+#[test]
+fn rollback_restores_the_base_tree() {
+    let out_dir = tempfile::tempdir().unwrap();
+    generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options: rollback_options(),
+        out: out_dir.path().join("release.tar"),
+    })
+    .unwrap();
+
+    let release = ReleaseArchive::open(&out_dir.path().join("release.tar")).unwrap();
+    assert!(!release.manifest.rollback);
+
+    let rollback = ReleaseArchive::open(&out_dir.path().join("release-rollback.tar")).unwrap();
+    assert!(rollback.manifest.rollback);
+    assert!(!rollback.manifest.mandatory);
+    assert_eq!(
+        rollback.manifest.label,
+        Label::Text(String::from("Rollback to v0.0.1"))
+    );
+    assert_eq!(rollback.manifest.versions(), Some(("v0.0.2", "v0.0.1")));
+    assert_eq!(rollback.manifest.constraints.from_versions, ["v0.0.2"]);
+    assert_eq!(check_release(&rollback), []);
+
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let applied = out_dir.path().join("applied");
+    copy_tree(&new, &applied).unwrap();
+    apply_release(&rollback, &applied).unwrap();
+    assert!(TreeSnapshot::new(&applied).unwrap().same_as(&base));
+}
+
+#[test]
+fn fan_out_lists_rollbacks() {
+    let out_dir = tempfile::tempdir().unwrap();
+    fan_out(FanOutRequest {
+        new_version: String::from("v0.0.3"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        bases: vec![(
+            String::from("v0.0.1"),
+            PathBuf::from("src/test/fixtures/base/"),
+        )],
+        options: rollback_options(),
+        out_dir: out_dir.path().to_path_buf(),
+    })
+    .unwrap();
+
+    let index: ReleaseIndex =
+        serde_json::from_slice(&std::fs::read(out_dir.path().join("index.json")).unwrap()).unwrap();
+    let rollback = index.releases[0].rollback.as_ref().unwrap();
+    assert_eq!(rollback.file, "release-v0.0.1-v0.0.3-rollback.tar");
+    let tar = std::fs::read(out_dir.path().join(&rollback.file)).unwrap();
+    assert_eq!(
+        hex::encode(<sha2::Sha256 as sha2::Digest>::digest(&tar)),
+        rollback.sha256
+    );
+}
+
+#[test]
+fn rollbacks_need_format_version_8() {
+    let out_dir = tempfile::tempdir().unwrap();
+    let result = generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options: ReleaseOptions {
+            format_version: 7,
+            ..rollback_options()
+        },
+        out: out_dir.path().join("release.tar"),
+    });
+
+    assert!(result.is_err());
+    assert_eq!(std::fs::read_dir(out_dir.path()).unwrap().count(), 0);
+}
@@ -0,0 +1,62 @@
+use {
+    super::release_options,
+    crate::{
+        GenerateRequest,
+        generate,
+        release_manifest::FORMAT_VERSION,
+        schema::{release_manifest_schema, validate},
+    },
+    serde_json::json,
+    std::{fs::File, path::PathBuf},
+};
+
+#[test]
+fn committed_schema_is_up_to_date() {
+    let path = format!("../../schemas/release-manifest.v{FORMAT_VERSION}.schema.json");
+    let committed: serde_json::Value = serde_json::from_reader(File::open(path).unwrap()).unwrap();
+
+    assert_eq!(
+        committed,
+        release_manifest_schema(),
+        "Run `just schemas` to regenerate the schemas"
+    );
+}
+
+#[test]
+fn generated_manifest_matches_schema() {
+    let out_dir = PathBuf::from("src/test/fixtures/out-schema");
+    let tar_path = out_dir.join("release.tar");
+    generate(GenerateRequest {
+        base_version: String::from("v0.0.1"),
+        base: PathBuf::from("src/test/fixtures/base/"),
+        new_version: String::from("v0.0.2"),
+        new: PathBuf::from("src/test/fixtures/new/"),
+        git: Default::default(),
+        options: release_options(),
+        out: tar_path.clone(),
+    })
+    .unwrap();
+
+    let mut tar = tar::Archive::new(File::open(tar_path).unwrap());
+    tar.unpack(&out_dir).unwrap();
+    let manifest: serde_json::Value =
+        serde_json::from_reader(File::open(out_dir.join("manifest.json")).unwrap()).unwrap();
+    std::fs::remove_dir_all(out_dir).unwrap();
+
+    assert_eq!(
+        validate(&release_manifest_schema(), &manifest),
+        Vec::<String>::new()
+    );
+}
+
+#[test]
+fn schema_rejects_unknown_actions() {
+    let manifest = json!({
+        "label": "test label",
+        "mandatory": false,
+        "date": "2025-01-01",
+        "actions": [{ "action": "format-disk" }],
+    });
+
+    assert!(!validate(&release_manifest_schema(), &manifest).is_empty());
+}
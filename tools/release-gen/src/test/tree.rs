@@ -0,0 +1,50 @@
+use {
+    crate::tree::{TreeDiff, TreeSnapshot},
+    std::{fs::Permissions, os::unix::fs::PermissionsExt, path::Path},
+};
+
+#[test]
+fn tree_diff() {
+    let base = TreeSnapshot::new(Path::new("src/test/fixtures/base/")).unwrap();
+    let new = TreeSnapshot::new(Path::new("src/test/fixtures/new/")).unwrap();
+    let diff = TreeDiff::new(&base, &new);
+
+    let paths = |files: &[&crate::tree::TreeFile]| {
+        files
+            .iter()
+            .map(|file| file.path.to_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(paths(&diff.deleted), ["dir1/subdir1/file2.txt"]);
+    assert_eq!(paths(&diff.added), ["dir2/file3.txt"]);
+    let changed: Vec<_> = diff
+        .changed
+        .iter()
+        .map(|(base_file, new_file)| {
+            assert_eq!(base_file.path, new_file.path);
+            base_file.path.to_str().unwrap()
+        })
+        .collect();
+    assert_eq!(changed, ["dir2/file1.txt", "dir2/file2.txt"]);
+
+    assert!(base.get(Path::new("dir1/subdir2/unchanged.txt")).is_some());
+    assert!(base.get(Path::new("dir2/file3.txt")).is_none());
+}
+
+#[test]
+fn unreadable_subdir_is_named_in_the_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let subdir = dir.path().join("apps/locked");
+    std::fs::create_dir_all(&subdir).unwrap();
+    std::fs::set_permissions(&subdir, Permissions::from_mode(0o000)).unwrap();
+    // Permissions don't stop root from reading the dir.
+    let readable = std::fs::read_dir(&subdir).is_ok();
+
+    let result = TreeSnapshot::new(dir.path());
+    std::fs::set_permissions(&subdir, Permissions::from_mode(0o755)).unwrap();
+    if readable {
+        return;
+    }
+    let err = format!("{:#}", result.unwrap_err());
+    assert!(err.contains(&subdir.display().to_string()), "{err}");
+}
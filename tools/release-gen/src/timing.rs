@@ -0,0 +1,52 @@
+use std::{
+    fmt,
+    time::{Duration, Instant},
+};
+
+/// How long each phase of generating releases took. Phases with the same name
+/// add up, e.g. when generating releases from several base versions.
+#[derive(Debug, Default)]
+pub struct Timings {
+    phases: Vec<(&'static str, Duration)>,
+}
+
+impl Timings {
+    /// Runs `f` and adds the time it took to the phase `name`.
+    pub fn time<T>(&mut self, name: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add_phase(name, start.elapsed());
+        result
+    }
+
+    /// Adds the phases of `other`.
+    pub fn add(&mut self, other: &Timings) {
+        for (name, duration) in &other.phases {
+            self.add_phase(name, *duration);
+        }
+    }
+
+    fn add_phase(&mut self, name: &'static str, elapsed: Duration) {
+        match self.phases.iter_mut().find(|(phase, _)| *phase == name) {
+            Some((_, total)) => *total += elapsed,
+            None => self.phases.push((name, elapsed)),
+        }
+    }
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Timing:")?;
+        let width = self
+            .phases
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        for (name, duration) in &self.phases {
+            writeln!(f, "  {name:<width$}  {:>8.3}s", duration.as_secs_f64())?;
+        }
+        let total: Duration = self.phases.iter().map(|(_, duration)| *duration).sum();
+        write!(f, "  {:<width$}  {:>8.3}s", "total", total.as_secs_f64())
+    }
+}
@@ -0,0 +1,234 @@
+use {
+    crate::filter::TreeFilter,
+    anyhow::Context,
+    rayon::prelude::*,
+    sha2::{Digest, Sha256},
+    std::{
+        collections::{HashMap, HashSet},
+        fs::{DirEntry, File},
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    },
+};
+
+/// A regular file inside of a [`TreeSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeFile {
+    /// Path relative to the root of the tree.
+    pub path: PathBuf,
+    pub size: u64,
+    pub sha256: [u8; 32],
+    /// Permission bits of the file.
+    pub mode: u32,
+}
+
+impl TreeFile {
+    /// Whether any of the executable bits is set. Only these bits are carried
+    /// over by releases, the others depend on the umask of whoever checked
+    /// out the tree.
+    pub fn is_executable(&self) -> bool {
+        self.mode & 0o111 != 0
+    }
+}
+
+/// A symbolic link inside of a [`TreeSnapshot`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TreeLink {
+    /// Path relative to the root of the tree.
+    pub path: PathBuf,
+    /// Where the link points to, as stored in the link.
+    pub target: PathBuf,
+}
+
+/// All regular files of a directory tree together with their sizes, hashes
+/// and modes, and all symbolic links.
+///
+/// Taking a snapshot reads every file once, hashing the files in parallel, so
+/// a snapshot can be compared against any number of other snapshots without
+/// touching the files again.
+#[derive(Debug)]
+pub struct TreeSnapshot {
+    pub root: PathBuf,
+    /// Files sorted by path.
+    pub files: Vec<TreeFile>,
+    /// Symbolic links sorted by path. Links are never followed.
+    pub links: Vec<TreeLink>,
+    /// Files and directories left out by the [`TreeFilter`], relative to the
+    /// root and sorted by path.
+    pub excluded: Vec<PathBuf>,
+    /// Index of each file in `files` by path.
+    index: HashMap<PathBuf, usize>,
+}
+
+impl TreeSnapshot {
+    pub fn new(root: &Path) -> anyhow::Result<Self> {
+        Self::filtered(root, &TreeFilter::none())
+    }
+
+    /// Takes a snapshot of the files not excluded by `filter`.
+    pub fn filtered(root: &Path, filter: &TreeFilter) -> anyhow::Result<Self> {
+        let mut entries = TreeEntries::default();
+        rec_get_all_files_in_tree(root, root, filter, &mut entries)
+            .with_context(|| format!("Getting all files in dir: {}", root.display()))?;
+        let files: Vec<TreeFile> = entries
+            .files
+            .into_par_iter()
+            .map(|(path, mode)| {
+                let (size, sha256) = hash_file(&root.join(&path))?;
+                Ok(TreeFile {
+                    path,
+                    size,
+                    sha256,
+                    mode,
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        let index = files
+            .iter()
+            .enumerate()
+            .map(|(index, file)| (file.path.clone(), index))
+            .collect();
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            files,
+            links: entries.links,
+            excluded: entries.excluded,
+            index,
+        })
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&TreeFile> {
+        self.index.get(path).map(|&index| &self.files[index])
+    }
+
+    /// Whether both trees have the same files, with the same contents and
+    /// executable bits, and the same links.
+    pub fn same_as(&self, other: &TreeSnapshot) -> bool {
+        self.links == other.links
+            && self.files.len() == other.files.len()
+            && self.files.iter().zip(&other.files).all(|(file, other)| {
+                file.path == other.path
+                    && file.sha256 == other.sha256
+                    && file.is_executable() == other.is_executable()
+            })
+    }
+
+    pub fn link(&self, path: &Path) -> Option<&TreeLink> {
+        self.links
+            .binary_search_by(|link| link.path.as_path().cmp(path))
+            .ok()
+            .map(|index| &self.links[index])
+    }
+
+    /// Full path of a file in this tree.
+    pub fn full_path(&self, path: &Path) -> PathBuf {
+        self.root.join(path)
+    }
+}
+
+/// Files that differ between two snapshots, each list sorted by path.
+#[derive(Debug)]
+pub struct TreeDiff<'a> {
+    /// Files only in the base tree.
+    pub deleted: Vec<&'a TreeFile>,
+    /// Files in both trees with different contents, as (base, new).
+    pub changed: Vec<(&'a TreeFile, &'a TreeFile)>,
+    /// Files only in the new tree.
+    pub added: Vec<&'a TreeFile>,
+}
+
+impl<'a> TreeDiff<'a> {
+    pub fn new(base: &'a TreeSnapshot, new: &'a TreeSnapshot) -> Self {
+        let base_paths: HashSet<&Path> =
+            base.files.iter().map(|file| file.path.as_path()).collect();
+        let new_paths: HashSet<&Path> = new.files.iter().map(|file| file.path.as_path()).collect();
+
+        let deleted = base
+            .files
+            .iter()
+            .filter(|file| !new_paths.contains(file.path.as_path()))
+            .collect();
+        let added = new
+            .files
+            .iter()
+            .filter(|file| !base_paths.contains(file.path.as_path()))
+            .collect();
+        let changed = base
+            .files
+            .iter()
+            .filter_map(|base_file| Some((base_file, new.get(&base_file.path)?)))
+            .filter(|(base_file, new_file)| {
+                base_file.size != new_file.size || base_file.sha256 != new_file.sha256
+            })
+            .collect();
+
+        Self {
+            deleted,
+            changed,
+            added,
+        }
+    }
+}
+
+/// Entries of a tree found by [`rec_get_all_files_in_tree`], with paths
+/// relative to the root.
+#[derive(Default)]
+struct TreeEntries {
+    /// Regular files and their permission bits.
+    files: Vec<(PathBuf, u32)>,
+    links: Vec<TreeLink>,
+    excluded: Vec<PathBuf>,
+}
+
+/// Collects all regular files and symbolic links in the tree not excluded by
+/// `filter`, sorted by path. Links are not followed.
+fn rec_get_all_files_in_tree(
+    root: &Path,
+    dir: &Path,
+    filter: &TreeFilter,
+    out: &mut TreeEntries,
+) -> anyhow::Result<()> {
+    let mut entries = std::fs::read_dir(dir)
+        .and_then(Iterator::collect::<Result<Vec<_>, _>>)
+        .with_context(|| format!("Reading dir: {}", dir.display()))?;
+    entries.sort_by_key(DirEntry::file_name);
+
+    for entry in entries {
+        let path = entry.path();
+        let metadata = entry
+            .metadata()
+            .with_context(|| format!("Reading metadata: {}", path.display()))?;
+        let relative = path
+            .strip_prefix(root)
+            .expect("Prefix should be valid")
+            .to_path_buf();
+
+        if filter.is_excluded(&relative, metadata.is_dir()) {
+            out.excluded.push(relative);
+        } else if metadata.is_symlink() {
+            let target = std::fs::read_link(&path)
+                .with_context(|| format!("Reading link: {}", path.display()))?;
+            out.links.push(TreeLink {
+                path: relative,
+                target,
+            });
+        } else if metadata.is_file() {
+            out.files
+                .push((relative, metadata.permissions().mode() & 0o777));
+        } else if metadata.is_dir() {
+            rec_get_all_files_in_tree(root, &path, filter, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Returns the size and the SHA-256 hash of the file.
+pub fn hash_file(path: &Path) -> anyhow::Result<(u64, [u8; 32])> {
+    let mut file = File::open(path).with_context(|| format!("Opening file: {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let size = std::io::copy(&mut file, &mut hasher)
+        .with_context(|| format!("Hashing file: {}", path.display()))?;
+    Ok((size, hasher.finalize().into()))
+}
@@ -0,0 +1,591 @@
+//! Signing of KeyOS firmware with `cosign2`: signing the files of a version
+//! folder, packing them into the firmware update tar, signing the tar and
+//! validating the signatures.
+//!
+//! The functions don't print anything, they return reports of what they did.
+//! The `signer` binary presents them on the command line.
+
+use anyhow::{Context, Result};
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SignerError {
+    #[error("File not found: {0}")]
+    FileNotFound(String),
+
+    #[error("Directory not found: {0}")]
+    DirectoryNotFound(String),
+
+    #[error("Failed to execute command: {0}")]
+    CommandFailed(String),
+
+    #[error("Not all files have two signatures")]
+    InsufficientSignatures {
+        /// Signatures of all the checked files.
+        signatures: Vec<FileStatus>,
+        /// Files that need to be signed with a second key.
+        unsigned: Vec<String>,
+    },
+}
+
+/// Version of the `manifest.json` JSON Schema. Bump it whenever [`Manifest`]
+/// changes.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A file of the firmware update together with its hash.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct FileEntry {
+    /// Path of the file relative to the version folder.
+    pub name: String,
+    /// SHA-256 of the signed file, as `0x` followed by 64 hex digits.
+    #[schemars(regex(pattern = r"^0x[0-9a-f]{64}$"))]
+    pub hash: String,
+}
+
+/// Contents of the manifest.json inside of the firmware update tar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Manifest {
+    /// Firmware version, as `v` followed by the version number.
+    pub version: String,
+    /// Files of the firmware update.
+    pub files: Vec<FileEntry>,
+}
+
+/// Which signatures the `cosign2` header of a file has.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SignatureStatus {
+    pub has_header: bool,
+    pub has_first_signature: bool,
+    pub has_second_signature: bool,
+}
+
+impl SignatureStatus {
+    const UNSIGNED: Self = Self {
+        has_header: false,
+        has_first_signature: false,
+        has_second_signature: false,
+    };
+
+    /// Reads the status from the output of `cosign2 dump`.
+    pub fn from_dump(success: bool, stdout: &str, stderr: &str) -> Result<Self> {
+        // Check if the file has no header
+        if !success || stderr.contains("no header found") || stdout.contains("no header found") {
+            return Ok(Self::UNSIGNED);
+        }
+
+        // Check for zero signatures in signature2
+        let re_sig2 = Regex::new(r"signature2.*0{64}")?;
+        if re_sig2.is_match(stdout) {
+            return Ok(Self {
+                has_header: true,
+                has_first_signature: true,
+                has_second_signature: false,
+            });
+        }
+
+        // Check for zero signatures in signature1
+        let re_sig1 = Regex::new(r"signature1.*0{64}")?;
+        if re_sig1.is_match(stdout) {
+            return Ok(Self {
+                has_header: true,
+                has_first_signature: false,
+                has_second_signature: false,
+            });
+        }
+
+        // If we get here, the file has two signatures
+        Ok(Self {
+            has_header: true,
+            has_first_signature: true,
+            has_second_signature: true,
+        })
+    }
+}
+
+/// Signature status of a file of a version folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct FileStatus {
+    pub path: String,
+    pub signatures: SignatureStatus,
+}
+
+/// What [`sign_files`] signed.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignFilesReport {
+    /// The KeyOS image, `app.bin`.
+    pub image: String,
+    /// The ELFs of the dynamically loadable apps.
+    pub apps: Vec<String>,
+    /// Whether the version folder has an `apps` directory.
+    pub has_apps_dir: bool,
+}
+
+/// What [`create_tar`] checked and packed.
+#[derive(Debug, Clone, Serialize)]
+pub struct CreateTarReport {
+    /// Signatures of the files going into the tar.
+    pub signatures: Vec<FileStatus>,
+    /// The generated `manifest.json`, with the hashes of the files.
+    pub manifest: Manifest,
+    /// Path of the created tar.
+    pub tar_file: String,
+    /// Files in the tar.
+    pub files: Vec<String>,
+}
+
+/// What [`sign_tar`] found and did.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignTarReport {
+    pub tar_file: String,
+    /// Signatures of the tar before signing.
+    pub before: SignatureStatus,
+    /// Whether a signature was added. Tars with two signatures are left as
+    /// they are.
+    pub signed: bool,
+}
+
+/// Result of [`validate`].
+#[derive(Debug, Clone, Serialize)]
+pub struct ValidationReport {
+    /// Signatures of the files that exist.
+    pub signatures: Vec<FileStatus>,
+    /// Files and directories that are missing.
+    pub missing_files: Vec<String>,
+    /// Files without two signatures.
+    pub unsigned_files: Vec<String>,
+    /// Number of app files found in the apps directory.
+    pub app_count: usize,
+}
+
+impl ValidationReport {
+    /// Whether all files exist and have two signatures.
+    pub fn is_valid(&self) -> bool {
+        self.missing_files.is_empty() && self.unsigned_files.is_empty()
+    }
+}
+
+/// Adds the `v` prefix of version folders if missing.
+pub fn normalize_version(version: &str) -> Result<String> {
+    // Ensure version has a 'v' prefix
+    if version.starts_with('v') {
+        Ok(version.to_string())
+    } else {
+        Ok(format!("v{}", version))
+    }
+}
+
+pub fn strip_v_prefix(version: &str) -> String {
+    // Remove 'v' prefix if present for cosign2 --binary-version parameter
+    version.strip_prefix('v').unwrap_or(version).to_string()
+}
+
+/// Path of the firmware update tar of a version.
+pub fn tar_path(version_folder: &str, firmware_version: &str) -> String {
+    format!("{}/KeyOS-v{}.bin", version_folder, firmware_version)
+}
+
+/// Signs `app.bin` and the app ELFs of the version folder in place.
+pub fn sign_files(
+    version_folder: &str,
+    config_path: &str,
+    firmware_version: &str,
+) -> Result<SignFilesReport> {
+    // Check if version folder exists
+    if !Path::new(version_folder).is_dir() {
+        return Err(SignerError::DirectoryNotFound(version_folder.to_string()).into());
+    }
+
+    // Check for required files
+    let app_bin = format!("{}/app.bin", version_folder);
+
+    if !Path::new(&app_bin).exists() {
+        return Err(SignerError::FileNotFound(app_bin).into());
+    }
+
+    cosign2_sign(&app_bin, config_path, firmware_version)?;
+
+    // Sign each dynamically loadable app
+    let apps_dir = format!("{}/apps", version_folder);
+    let has_apps_dir = Path::new(&apps_dir).is_dir();
+    let mut apps = Vec::new();
+    for (elf_path, _manifest_path) in app_dirs(version_folder)? {
+        let app_path = elf_path.to_str().unwrap();
+        cosign2_sign(app_path, config_path, firmware_version)?;
+        apps.push(app_path.to_string());
+    }
+
+    Ok(SignFilesReport {
+        image: app_bin,
+        apps,
+        has_apps_dir,
+    })
+}
+
+/// Generates the `manifest.json` and packs the firmware update tar, once
+/// `app.bin` and the app ELFs have two signatures. With
+/// `allow_one_signature`, the files are packed whatever their signatures.
+pub fn create_tar(
+    version_folder: &str,
+    firmware_version: &str,
+    allow_one_signature: bool,
+) -> Result<CreateTarReport> {
+    // Check if version folder exists
+    if !Path::new(version_folder).is_dir() {
+        return Err(SignerError::DirectoryNotFound(version_folder.to_string()).into());
+    }
+
+    let app_bin = format!("{}/app.bin", version_folder);
+    let mut signatures = vec![FileStatus {
+        signatures: check_signatures(&app_bin)?,
+        path: app_bin,
+    }];
+
+    // Check all app files
+    for (elf_path, _manifest_path) in app_dirs(version_folder)? {
+        let elf_path = elf_path.to_string_lossy().to_string();
+        signatures.push(FileStatus {
+            signatures: check_signatures(&elf_path)?,
+            path: elf_path,
+        });
+    }
+
+    // Only proceed with tar file creation if all files are properly signed
+    let unsigned = unsigned_files(&signatures, allow_one_signature);
+    if !unsigned.is_empty() {
+        return Err(SignerError::InsufficientSignatures {
+            signatures,
+            unsigned,
+        }
+        .into());
+    }
+
+    let manifest = generate_manifest(version_folder, firmware_version)?;
+
+    // Create tar file
+    let tar_file = tar_path(version_folder, firmware_version);
+
+    // Collect all files to include in the tar
+    let mut files_to_include = vec![
+        format!("{}/app.bin", version_folder),
+        format!("{}/manifest.json", version_folder),
+    ];
+
+    // Add all .elf files in the apps directory
+    for (elf_path, manifest_path) in app_dirs(version_folder)? {
+        files_to_include.push(elf_path.to_string_lossy().to_string());
+        files_to_include.push(manifest_path.to_string_lossy().to_string());
+    }
+
+    // Build the tar command with explicit file list
+    let mut tar_cmd = Command::new("tar");
+    tar_cmd.arg("-cf").arg(&tar_file);
+
+    // Add all collected files
+    for file in &files_to_include {
+        tar_cmd.arg(file);
+    }
+
+    // Execute the tar command
+    let output = tar_cmd.output().context("Failed to execute tar command")?;
+
+    if !output.status.success() {
+        return Err(SignerError::CommandFailed(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ))
+        .context("Failed to create tar file");
+    }
+
+    if !Path::new(&tar_file).exists() {
+        return Err(SignerError::FileNotFound(tar_file).into());
+    }
+
+    Ok(CreateTarReport {
+        signatures,
+        manifest,
+        tar_file,
+        files: files_to_include,
+    })
+}
+
+/// Files that still need a second signature before they can be packed.
+fn unsigned_files(signatures: &[FileStatus], allow_one_signature: bool) -> Vec<String> {
+    if allow_one_signature {
+        return vec![];
+    }
+    signatures
+        .iter()
+        .filter(|file| !file.signatures.has_second_signature)
+        .map(|file| file.path.clone())
+        .collect()
+}
+
+/// Adds the next signature to the firmware update tar, unless it already has
+/// two.
+pub fn sign_tar(
+    version_folder: &str,
+    config_path: &str,
+    firmware_version: &str,
+) -> Result<SignTarReport> {
+    let tar_file = tar_path(version_folder, firmware_version);
+
+    // Check if tar file exists
+    if !Path::new(&tar_file).exists() {
+        return Err(SignerError::FileNotFound(format!(
+            "Tar file not found: {}. Please run create-tar command first.",
+            tar_file
+        ))
+        .into());
+    }
+
+    let before = check_signatures(&tar_file)?;
+    let signed = !before.has_second_signature;
+    if signed {
+        cosign2_sign(&tar_file, config_path, firmware_version)
+            .context("Failed to sign tar file")?;
+    }
+
+    Ok(SignTarReport {
+        tar_file,
+        before,
+        signed,
+    })
+}
+
+/// Checks that all files of a version exist and have two signatures.
+pub fn validate(version_folder: &str, firmware_version: &str) -> Result<ValidationReport> {
+    // Check if version folder exists
+    if !Path::new(version_folder).is_dir() {
+        return Err(SignerError::DirectoryNotFound(version_folder.to_string()).into());
+    }
+
+    let mut report = ValidationReport {
+        signatures: Vec::new(),
+        missing_files: Vec::new(),
+        unsigned_files: Vec::new(),
+        app_count: 0,
+    };
+    let mut check = |path: &str, name: String| -> Result<()> {
+        let signatures = check_signatures(path)?;
+        if !signatures.has_second_signature {
+            report.unsigned_files.push(name);
+        }
+        report.signatures.push(FileStatus {
+            path: path.to_string(),
+            signatures,
+        });
+        Ok(())
+    };
+
+    // Check app.bin
+    let app_bin = format!("{}/app.bin", version_folder);
+    let app_bin_exists = Path::new(&app_bin).exists();
+    if app_bin_exists {
+        check(&app_bin, "app.bin".to_string())?;
+    }
+
+    // Check all app files
+    let apps_dir = format!("{}/apps", version_folder);
+    let apps_path = Path::new(&apps_dir);
+    let mut app_count = 0;
+    if apps_path.is_dir() {
+        for path in app_files(apps_path)? {
+            app_count += 1;
+            let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+            check(path.to_str().unwrap(), format!("apps/{}", file_name))?;
+        }
+    }
+
+    // Check KeyOS tar file
+    let tar_file = tar_path(version_folder, firmware_version);
+    let tar_name = format!("KeyOS-v{}.bin", firmware_version);
+    let tar_exists = Path::new(&tar_file).exists();
+    if tar_exists {
+        check(&tar_file, tar_name.clone())?;
+    }
+
+    report.app_count = app_count;
+    if !app_bin_exists {
+        report.missing_files.push("app.bin".to_string());
+    }
+    if !Path::new(&format!("{}/manifest.json", version_folder)).exists() {
+        report.missing_files.push("manifest.json".to_string());
+    }
+    if !apps_path.is_dir() {
+        report.missing_files.push("apps/".to_string());
+    }
+    if !tar_exists {
+        report.missing_files.push(tar_name);
+    }
+
+    Ok(report)
+}
+
+/// Reads which signatures `file_path` has with `cosign2 dump`.
+pub fn check_signatures(file_path: &str) -> Result<SignatureStatus> {
+    // Run cosign2 dump and capture output
+    let output = Command::new("cosign2")
+        .args(["dump", "--input", file_path])
+        .output()
+        .context(format!("Failed to execute cosign2 dump for {}", file_path))?;
+
+    SignatureStatus::from_dump(
+        output.status.success(),
+        &String::from_utf8_lossy(&output.stdout),
+        &String::from_utf8_lossy(&output.stderr),
+    )
+}
+
+/// Signs `file_path` in place with `cosign2`.
+fn cosign2_sign(file_path: &str, config_path: &str, firmware_version: &str) -> Result<()> {
+    let output = Command::new("cosign2")
+        .args([
+            "sign",
+            "-i",
+            file_path,
+            "-c",
+            config_path,
+            "--in-place",
+            "--binary-version",
+            firmware_version,
+        ])
+        .output()
+        .context(format!("Failed to execute cosign2 for {}", file_path))?;
+
+    if !output.status.success() {
+        return Err(SignerError::CommandFailed(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ))
+        .context(format!("Failed to sign {}", file_path));
+    }
+    Ok(())
+}
+
+/// The app ELFs and manifests of the dynamically loadable apps, one
+/// directory per app under `apps/`.
+fn app_dirs(version_folder: &str) -> Result<Vec<(PathBuf, PathBuf)>> {
+    let apps_dir = format!("{}/apps", version_folder);
+    let apps_path = Path::new(&apps_dir);
+    let mut apps = Vec::new();
+
+    if apps_path.is_dir() {
+        for entry in fs::read_dir(apps_path).context("Failed to read apps directory")? {
+            let entry = entry.context("Failed to read directory entry")?;
+            let path = entry.path();
+
+            // Found an app dir, it should contain an app .elf and a manifest
+            if path.is_dir() {
+                let elf_path = path.join("app.elf");
+                let manifest_path = path.join("manifest.json");
+                if elf_path.exists() && manifest_path.exists() {
+                    apps.push((elf_path, manifest_path));
+                }
+            }
+        }
+    }
+    Ok(apps)
+}
+
+/// The `gui-app*.elf` files directly in `apps_path`.
+fn app_files(apps_path: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(apps_path).context("Failed to read apps directory")? {
+        let entry = entry.context("Failed to read directory entry")?;
+        let path = entry.path();
+
+        if path.is_file() && path.extension().is_some_and(|ext| ext == "elf") {
+            if let Some(file_name) = path.file_name().and_then(|n| n.to_str()) {
+                if file_name.starts_with("gui-app") {
+                    files.push(path);
+                }
+            }
+        }
+    }
+    Ok(files)
+}
+
+/// Writes the `manifest.json` of the version folder and returns it.
+pub fn generate_manifest(version_folder: &str, firmware_version: &str) -> Result<Manifest> {
+    let manifest_file = format!("{}/manifest.json", version_folder);
+
+    // Create manifest structure
+    let mut manifest = Manifest {
+        version: format!("v{}", firmware_version),
+        files: Vec::new(),
+    };
+
+    // Add app.bin to manifest
+    let app_bin = format!("{}/app.bin", version_folder);
+    let app_hash = calculate_hash(&app_bin)?;
+    manifest.files.push(FileEntry {
+        name: "app.bin".to_string(),
+        hash: format!("0x{}", app_hash),
+    });
+
+    // Add each app to manifest
+    let apps_dir = format!("{}/apps", version_folder);
+    let apps_path = Path::new(&apps_dir);
+
+    if apps_path.is_dir() {
+        for path in app_files(apps_path)? {
+            let file_name = path.file_name().unwrap().to_string_lossy();
+            let app_hash = calculate_hash(path.to_str().unwrap())?;
+
+            manifest.files.push(FileEntry {
+                name: format!("apps/{}", file_name),
+                hash: format!("0x{}", app_hash),
+            });
+        }
+    }
+
+    // Write manifest to file
+    let manifest_json =
+        serde_json::to_string_pretty(&manifest).context("Failed to serialize manifest to JSON")?;
+
+    fs::write(&manifest_file, manifest_json)
+        .context(format!("Failed to write manifest file: {}", manifest_file))?;
+    Ok(manifest)
+}
+
+pub fn calculate_hash(file_path: &str) -> Result<String> {
+    let mut file =
+        File::open(file_path).context(format!("Failed to open file for hashing: {}", file_path))?;
+
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)
+        .context(format!("Failed to read file for hashing: {}", file_path))?;
+
+    let hash = hasher.finalize();
+    Ok(hex::encode(hash))
+}
+
+pub fn manifest_schema() -> serde_json::Value {
+    let mut schema = schemars::schema_for!(Manifest);
+    schema.insert(
+        "$id".to_string(),
+        serde_json::json!(format!("urn:keyos:firmware-manifest:v{}", SCHEMA_VERSION)),
+    );
+    schema.to_value()
+}
+
+/// Validates a `manifest.json` against [`manifest_schema`]. Returns the
+/// errors, as `<instance path>: <error>`.
+pub fn validate_manifest(manifest: &serde_json::Value) -> Result<Vec<String>> {
+    let schema = manifest_schema();
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|err| anyhow::anyhow!("Invalid manifest schema: {}", err))?;
+    Ok(validator
+        .iter_errors(manifest)
+        .map(|error| format!("{}: {}", error.instance_path(), error))
+        .collect())
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,114 @@
+use super::*;
+
+const ZERO: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+const SIGNATURE: &str = "3045022100a1b2c3d4e5f60718293a4b5c6d7e8f90a1b2c3d4e5f60718293a4b5c";
+
+fn dump(signature1: &str, signature2: &str) -> String {
+    format!(
+        "magic: SFFK\nsignature1: {}\nsignature2: {}\n",
+        signature1, signature2
+    )
+}
+
+#[test]
+fn signatures_are_read_from_the_dump() {
+    let status = |success, stdout: &str, stderr| {
+        let status = SignatureStatus::from_dump(success, stdout, stderr).unwrap();
+        (
+            status.has_header,
+            status.has_first_signature,
+            status.has_second_signature,
+        )
+    };
+
+    assert_eq!(status(false, "", "error"), (false, false, false));
+    assert_eq!(status(true, "", "no header found"), (false, false, false));
+    assert_eq!(status(true, &dump(ZERO, ZERO), ""), (true, true, false));
+    assert_eq!(
+        status(true, &dump(SIGNATURE, ZERO), ""),
+        (true, true, false)
+    );
+    assert_eq!(
+        status(true, &dump(ZERO, SIGNATURE), ""),
+        (true, false, false)
+    );
+    assert_eq!(
+        status(true, &dump(SIGNATURE, SIGNATURE), ""),
+        (true, true, true)
+    );
+}
+
+#[test]
+fn missing_files_are_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    let version_folder = dir.path().to_str().unwrap();
+
+    let report = validate(version_folder, "1.0.0").unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(
+        report.missing_files,
+        ["app.bin", "manifest.json", "apps/", "KeyOS-v1.0.0.bin"]
+    );
+    assert!(report.signatures.is_empty());
+
+    let err = validate(&format!("{}/missing", version_folder), "1.0.0").unwrap_err();
+    assert!(matches!(
+        err.downcast_ref(),
+        Some(SignerError::DirectoryNotFound(_))
+    ));
+}
+
+#[test]
+fn manifest_lists_the_hashes() {
+    let dir = tempfile::tempdir().unwrap();
+    let version_folder = dir.path().to_str().unwrap();
+    fs::write(dir.path().join("app.bin"), "image").unwrap();
+    fs::create_dir(dir.path().join("apps")).unwrap();
+    fs::write(dir.path().join("apps/gui-app-settings.elf"), "app").unwrap();
+    fs::write(dir.path().join("apps/other.elf"), "other").unwrap();
+
+    let manifest = generate_manifest(version_folder, "1.0.0").unwrap();
+    assert_eq!(manifest.version, "v1.0.0");
+    let names: Vec<_> = manifest
+        .files
+        .iter()
+        .map(|file| file.name.as_str())
+        .collect();
+    assert_eq!(names, ["app.bin", "apps/gui-app-settings.elf"]);
+    assert_eq!(
+        manifest.files[0].hash,
+        format!("0x{}", hex::encode(Sha256::digest(b"image")))
+    );
+
+    let written: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.path().join("manifest.json")).unwrap()).unwrap();
+    assert_eq!(written, serde_json::to_value(&manifest).unwrap());
+    assert_eq!(validate_manifest(&written).unwrap(), Vec::<String>::new());
+}
+
+#[test]
+fn apps_need_two_signatures_too() {
+    let file = |path: &str, has_second_signature| FileStatus {
+        path: path.to_string(),
+        signatures: SignatureStatus {
+            has_header: true,
+            has_first_signature: true,
+            has_second_signature,
+        },
+    };
+    let signatures = [
+        file("1.0.0/app.bin", true),
+        file("1.0.0/apps/settings/app.elf", false),
+        file("1.0.0/apps/other/app.elf", true),
+    ];
+
+    assert_eq!(
+        unsigned_files(&signatures, false),
+        ["1.0.0/apps/settings/app.elf"]
+    );
+    assert!(unsigned_files(&signatures, true).is_empty());
+
+    let signatures = [file("1.0.0/app.bin", false)];
+    assert_eq!(unsigned_files(&signatures, false), ["1.0.0/app.bin"]);
+    assert!(unsigned_files(&signatures, true).is_empty());
+}